@@ -0,0 +1,141 @@
+//! Busy-waiting on a `ConcurrentQueue`, as `serve` used to, against waiting on
+//! a `Queue`.
+//!
+//! `idle_waiter` runs a CPU-bound workload on a two-thread runtime while a
+//! task waits for a proxy that never comes; a spinning waiter takes a whole
+//! worker thread away from the workload. `handoff` passes items through a
+//! queue bounded like `LIVE_PROXIES`.
+
+use std::{
+    hint::black_box,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use concurrent_queue::ConcurrentQueue;
+use criterion::{criterion_group, criterion_main, Criterion};
+use proxy_rs::utils::queue::Queue;
+use tokio::{runtime::Runtime, task::JoinHandle};
+
+const WORKERS: usize = 2;
+const ITEMS: usize = 1000;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(WORKERS)
+        .build()
+        .unwrap()
+}
+
+fn workload() -> u64 {
+    (0..200_000u64).fold(0, |acc, n| acc.wrapping_add(black_box(n) * n))
+}
+
+async fn run_workload() {
+    let tasks: Vec<JoinHandle<u64>> = (0..WORKERS)
+        .map(|_| tokio::spawn(async { workload() }))
+        .collect();
+    for task in tasks {
+        black_box(task.await.unwrap());
+    }
+}
+
+fn idle_waiter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("idle_waiter");
+
+    group.bench_function("none", |b| {
+        let rt = runtime();
+        b.iter(|| rt.block_on(run_workload()));
+    });
+
+    group.bench_function("spin", |b| {
+        let rt = runtime();
+        let queue = Arc::new(ConcurrentQueue::<u32>::bounded(20));
+        let stop = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let (queue, stop) = (Arc::clone(&queue), Arc::clone(&stop));
+            rt.spawn(async move {
+                while queue.is_empty() && !stop.load(Ordering::Relaxed) {
+                    continue;
+                }
+            })
+        };
+        b.iter(|| rt.block_on(run_workload()));
+        stop.store(true, Ordering::Relaxed);
+        rt.block_on(waiter).unwrap();
+    });
+
+    group.bench_function("notify", |b| {
+        let rt = runtime();
+        let queue = Arc::new(Queue::<u32>::bounded(20));
+        let waiter = {
+            let queue = Arc::clone(&queue);
+            rt.spawn(async move { queue.wait_for_item().await })
+        };
+        b.iter(|| rt.block_on(run_workload()));
+        queue.push(0).unwrap();
+        rt.block_on(waiter).unwrap();
+    });
+
+    group.finish();
+}
+
+fn handoff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handoff");
+
+    group.bench_function("spin", |b| {
+        let rt = runtime();
+        b.iter(|| {
+            rt.block_on(async {
+                let queue = Arc::new(ConcurrentQueue::bounded(20));
+                let producer = {
+                    let queue = Arc::clone(&queue);
+                    tokio::spawn(async move {
+                        for item in 0..ITEMS {
+                            while queue.is_full() {
+                                continue;
+                            }
+                            queue.push(item).unwrap();
+                        }
+                    })
+                };
+                let mut received = 0;
+                while received < ITEMS {
+                    if let Ok(item) = queue.pop() {
+                        black_box(item);
+                        received += 1;
+                    }
+                }
+                producer.await.unwrap();
+            })
+        });
+    });
+
+    group.bench_function("notify", |b| {
+        let rt = runtime();
+        b.iter(|| {
+            rt.block_on(async {
+                let queue = Arc::new(Queue::bounded(20));
+                let producer = {
+                    let queue = Arc::clone(&queue);
+                    tokio::spawn(async move {
+                        for item in 0..ITEMS {
+                            queue.push_wait(item).await.unwrap();
+                        }
+                    })
+                };
+                for _ in 0..ITEMS {
+                    black_box(queue.pop_wait().await);
+                }
+                producer.await.unwrap();
+            })
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, idle_waiter, handoff);
+criterion_main!(benches);
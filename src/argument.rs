@@ -1,6 +1,17 @@
-use clap::builder::PossibleValue;
+use clap::builder::{PossibleValue, PossibleValuesParser, TypedValueParser};
 use clap::{Args, Parser, Subcommand};
 
+use ipnet::IpNet;
+use proxy_rs::{
+    proxy::Credentials,
+    server::{
+        client::{parse_net, parse_user},
+        session::SessionKey,
+        strategy::Strategy,
+    },
+    types::{AnonymityLevel, ProxyProtocol},
+};
+
 #[derive(Parser, Debug, Clone)]
 #[command(after_help = "Suggestions and bug reports are greatly appreciated:
 https://github.com/zevtyardt/proxy.rs/issues")]
@@ -28,6 +39,18 @@ pub struct Cli {
     #[arg(long)]
     pub skip_version_check: bool,
 
+    /// Serve Prometheus metrics at http://<ADDR>/metrics, e.g. 127.0.0.1:9100
+    #[arg(long, value_name = "ADDR")]
+    pub metrics: Option<String>,
+
+    /// SQLite database keeping the history of checked proxies across runs of find and serve
+    #[arg(long, value_name = "PATH")]
+    pub db: Option<std::path::PathBuf>,
+
+    /// Also store proxy usernames and passwords in --db, in plain text
+    #[arg(long, requires = "db")]
+    pub db_credentials: bool,
+
     #[command(subcommand)]
     pub sub: Commands,
 }
@@ -44,6 +67,9 @@ pub enum Commands {
 
     /// Run a local proxy server [BETA]
     Serve(ServeArgs),
+
+    /// Check proxies from a previous json output again and report what changed
+    Recheck(RecheckArgs),
 }
 
 #[derive(Args, Debug, Clone)]
@@ -79,31 +105,16 @@ pub struct GrabArgs {
 https://github.com/zevtyardt/proxy.rs/issues")]
 pub struct FindArgs {
     /// Type(s) (protocols) that need to be check on support by proxy
-    #[arg(long, required = true, num_args(1..),
-        value_parser([
-            PossibleValue::new("HTTP"),
-            PossibleValue::new("HTTPS"),
-            PossibleValue::new("SOCKS4"),
-            PossibleValue::new("SOCKS5"),
-            PossibleValue::new("CONNECT:80"),
-            PossibleValue::new("CONNECT:25"),
-        ]),
-    )]
-    pub types: Vec<String>,
+    #[arg(long, required = true, num_args(1..), value_enum)]
+    pub types: Vec<ProxyProtocol>,
 
     /// Path to the file with proxies. If specified, used instead of providers
     #[arg(long, num_args(1..))]
     pub files: Vec<std::path::PathBuf>,
 
     /// Level(s) of anonymity (for HTTP only). By default, any level
-    #[arg(long, num_args(1..),
-        value_parser([
-            PossibleValue::new("Transparent"),
-            PossibleValue::new("Anonymous"),
-            PossibleValue::new("High")
-        ])
-    )]
-    pub levels: Vec<String>,
+    #[arg(long, num_args(1..), value_enum)]
+    pub levels: Vec<AnonymityLevel>,
 
     /// The maximum number of attempts to check a proxy
     #[arg(long, default_value = "1")]
@@ -141,6 +152,44 @@ pub struct FindArgs {
     pub outfile: Option<std::path::PathBuf>,
 }
 
+#[derive(Args, Debug, Clone)]
+#[command(after_help = "Suggestions and bug reports are greatly appreciated:
+https://github.com/zevtyardt/proxy.rs/issues")]
+pub struct RecheckArgs {
+    /// Proxies in the json format of find, e.g. from `find --format json --outfile proxies.json`
+    pub file: std::path::PathBuf,
+
+    /// Type(s) (protocols) that need to be check on support by proxy. By default, all of them
+    #[arg(long, num_args(1..), value_enum)]
+    pub types: Vec<ProxyProtocol>,
+
+    /// The maximum number of attempts to check a proxy
+    #[arg(long, default_value = "1")]
+    pub max_tries: usize,
+
+    /// Also report proxies that did not change
+    #[arg(long)]
+    pub all: bool,
+
+    /// Flag indicating in what format the changes will be presented.
+    #[arg(short, long,
+        default_value = "text",
+        value_parser([
+            PossibleValue::new("text"),
+            PossibleValue::new("json")
+        ])
+    )]
+    pub format: String,
+
+    /// Save changes to file. By default, output to console
+    #[arg(short, long)]
+    pub outfile: Option<std::path::PathBuf>,
+
+    /// Save the rechecked proxies, including the dead ones, to recheck them later
+    #[arg(long, value_name = "PATH")]
+    pub save: Option<std::path::PathBuf>,
+}
+
 #[derive(Args, Debug, Clone)]
 #[command(after_help = "Suggestions and bug reports are greatly appreciated:
 https://github.com/zevtyardt/proxy.rs/issues")]
@@ -153,31 +202,76 @@ pub struct ServeArgs {
     #[arg(long, default_value = "8080")]
     pub port: u16,
 
+    /// Port of an additional SOCKS5-only listener. SOCKS5 clients are also accepted on --port
+    #[arg(long)]
+    pub socks_port: Option<u16>,
+
+    /// Port of an admin API to inspect and manage the proxy pool
+    #[arg(long)]
+    pub admin_port: Option<u16>,
+
+    /// Host of the admin API
+    #[arg(long, default_value = "127.0.0.1")]
+    pub admin_host: String,
+
+    /// The maximum number of upstream proxies to try before answering with 502 Bad Gateway
+    #[arg(long, default_value = "3")]
+    pub max_attempts: usize,
+
+    /// How upstream proxies are picked from the pool
+    #[arg(long, default_value = "best", value_enum)]
+    pub strategy: Strategy,
+
+    /// Keep the same upstream for requests of a session, identified by the first key the client provides
+    #[arg(long, num_args(1..), value_enum)]
+    pub sticky: Vec<SessionKey>,
+
+    /// Seconds a sticky session keeps its upstream after its last request
+    #[arg(long, default_value = "600")]
+    pub session_ttl: u64,
+
+    /// Seconds between health checks of the proxies in the pool, 0 disables them
+    #[arg(long, default_value = "60")]
+    pub health_interval: u64,
+
+    /// The maximum number of proxies health checked at once
+    #[arg(long, default_value = "10")]
+    pub health_concurrency: usize,
+
+    /// Require clients to authenticate as one of these users, e.g. alice:secret
+    #[arg(long, num_args(1..), value_name = "USER:PASS", value_parser = parse_user)]
+    pub auth: Vec<Credentials>,
+
+    /// Read additional users from a file with one user:pass per line
+    #[arg(long, value_name = "PATH")]
+    pub auth_file: Option<std::path::PathBuf>,
+
+    /// Only accept clients from these networks, e.g. 10.0.0.0/8 or 127.0.0.1
+    #[arg(long, num_args(1..), value_name = "CIDR", value_parser = parse_net)]
+    pub allow: Vec<IpNet>,
+
+    /// Reject clients from these networks, takes precedence over --allow
+    #[arg(long, num_args(1..), value_name = "CIDR", value_parser = parse_net)]
+    pub deny: Vec<IpNet>,
+
     /// Type(s) (protocols) that need to be check on support by proxy
     #[arg(long, required = true, num_args(1..),
-        value_parser([
-            PossibleValue::new("HTTP"),
-            PossibleValue::new("HTTPS"),
-            PossibleValue::new("SOCKS4"),
-            PossibleValue::new("SOCKS5"),
-            PossibleValue::new("CONNECT:80"),
-        ]),
+        value_parser(
+            PossibleValuesParser::new([
+                "HTTP", "HTTPS", "SOCKS4", "SOCKS4a", "SOCKS5", "CONNECT:80"
+            ])
+                .try_map(|s| s.parse::<ProxyProtocol>())
+        ),
     )]
-    pub types: Vec<String>,
+    pub types: Vec<ProxyProtocol>,
 
     /// Path to the file with proxies. If specified, used instead of providers
     #[arg(long, num_args(1..))]
     pub files: Vec<std::path::PathBuf>,
 
     /// Level(s) of anonymity (for HTTP only). By default, any level
-    #[arg(long, num_args(1..),
-        value_parser([
-            PossibleValue::new("Transparent"),
-            PossibleValue::new("Anonymous"),
-            PossibleValue::new("High")
-        ])
-    )]
-    pub levels: Vec<String>,
+    #[arg(long, num_args(1..), value_enum)]
+    pub levels: Vec<AnonymityLevel>,
 
     /// The maximum number of attempts to check a proxy
     #[arg(long, default_value = "1")]
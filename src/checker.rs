@@ -1,4 +1,13 @@
-use std::{collections::BTreeMap, process::exit, sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use dashmap::{DashMap, DashSet};
 use futures_util::{stream::FuturesUnordered, StreamExt};
@@ -6,42 +15,90 @@ use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use rand::{seq::SliceRandom, thread_rng};
 use regex::Regex;
-use tokio::{sync::Semaphore, time};
+use tokio::{
+    sync::{Notify, Semaphore},
+    time,
+};
 
 use crate::{
+    error::ProxyError,
     judge::{check_judge_host, get_judges, Judge},
-    negotiators::{
-        connect_25::Connect25Negotiator, connect_80::Connect80Negotiator, http::HttpNegotiator,
-        https::HttpsNegotiator, socks4::Socks4Negotiator, socks5::Socks5Negotiator,
-    },
-    proxy::Proxy,
+    metrics::{self, CHECKS},
+    negotiators::{get_negotiator, NEGOTIATORS},
+    proxy::{Credentials, Proxy},
     resolver::Resolver,
-    utils::{
-        geolite_database::DOWNLOADING,
-        http::{get_headers, response::ResponseParser},
-        vec_of_strings,
-    },
+    types::{AnonymityLevel, ProxyProtocol},
+    utils::http::{get_headers, response::ResponseParser},
 };
 
 lazy_static! {
-    static ref ENABLE_PROTOCOLS: Mutex<DashSet<String>> = Mutex::new(DashSet::new());
+    static ref ENABLE_PROTOCOLS: Mutex<DashSet<ProxyProtocol>> = Mutex::new(DashSet::new());
     static ref JUDGES: DashMap<String, Vec<Judge>> = DashMap::new();
+    /// Notified when a judge is added, and once all of them were checked.
+    static ref JUDGES_FOUND: Notify = Notify::new();
 }
 
-pub async fn check_judges(ssl: bool, ext_ip: String, mut expected_types: Vec<String>) {
-    let stime = time::Instant::now();
-    if !expected_types.contains(&"SMTP".to_string())
-        && expected_types.contains(&"CONNECT:25".to_string())
-    {
-        expected_types.push("SMTP".to_string());
-    }
+static JUDGES_CHECKED: AtomicBool = AtomicBool::new(false);
 
-    if !expected_types.contains(&"HTTP".to_string())
-        && ["CONNECT:80", "SOCKS4", "SOCKS5"]
-            .iter()
-            .any(|x| expected_types.contains(&x.to_string()))
+const IP_PATTERN: &str = r#"\d+\.\d+\.\d+\.\d+|[0-9a-f]{1,4}(?::[0-9a-f]{0,4}){2,7}"#;
+
+/// Ip addresses in `text`. Candidates that do not parse, like the clock time
+/// of a `Date` header, are skipped.
+fn find_ips<'a>(ip_re: &'a Regex, text: &'a str) -> impl Iterator<Item = &'a str> {
+    ip_re
+        .find_iter(text)
+        .map(|m| m.as_str())
+        .filter(|candidate| candidate.parse::<IpAddr>().is_ok())
+}
+
+fn protocols_for_scheme(scheme: &str) -> Vec<ProxyProtocol> {
+    let registry = NEGOTIATORS.read();
+    registry
+        .protocols()
+        .into_iter()
+        .filter(|proto| {
+            registry
+                .get(proto)
+                .map(|negotiator| negotiator.judge_scheme() == scheme)
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+/// Urls of the working judges, by scheme.
+pub fn working_judges() -> BTreeMap<String, Vec<String>> {
+    JUDGES
+        .iter()
+        .map(|entry| {
+            let urls = entry.value().iter().map(|judge| judge.url.to_string());
+            (entry.key().clone(), urls.collect())
+        })
+        .collect()
+}
+
+/// Checks the judges used by `expected_types`, failing when none of them works.
+pub async fn check_judges(
+    ssl: bool,
+    ext_ip: String,
+    expected_types: Vec<ProxyProtocol>,
+) -> Result<(), ProxyError> {
+    let stime = time::Instant::now();
+    // a previous call may have found no judges for a scheme checked again now
+    JUDGES_CHECKED.store(false, Ordering::SeqCst);
+    let mut expected_schemes: Vec<String> = vec![];
     {
-        expected_types.push("HTTP".to_string());
+        let registry = NEGOTIATORS.read();
+        for proto in registry.protocols() {
+            if !expected_types.contains(&proto) {
+                continue;
+            }
+            if let Some(negotiator) = registry.get(&proto) {
+                let scheme = negotiator.judge_scheme().to_string();
+                if !expected_schemes.contains(&scheme) {
+                    expected_schemes.push(scheme);
+                }
+            }
+        }
     }
 
     let mut futures = FuturesUnordered::new();
@@ -49,12 +106,11 @@ pub async fn check_judges(ssl: bool, ext_ip: String, mut expected_types: Vec<Str
 
     for mut judge in get_judges() {
         let permit = Arc::clone(&sem).acquire_owned().await;
-        let expected_types = expected_types.clone();
+        let expected_schemes = expected_schemes.clone();
         let ext_ip = ext_ip.clone();
-        let ssl = ssl;
         futures.push(tokio::spawn(async move {
             let _ = permit;
-            if expected_types.contains(&judge.scheme) {
+            if expected_schemes.contains(&judge.scheme) {
                 judge.verify_ssl = ssl;
                 check_judge_host(&mut judge, &ext_ip).await;
             }
@@ -68,40 +124,35 @@ pub async fn check_judges(ssl: bool, ext_ip: String, mut expected_types: Vec<Str
 
     while let Some(Ok(judge)) = futures.next().await {
         if judge.is_working {
-            if !JUDGES.contains_key(&judge.scheme) {
-                JUDGES.insert(judge.scheme.clone(), vec![]);
-            }
-            if let Some(mut v) = JUDGES.get_mut(&judge.scheme) {
-                v.push(judge);
-                working += 1;
-            }
+            let mut judges = JUDGES.entry(judge.scheme.clone()).or_default();
+            // judges found by a previous call are replaced, not duplicated
+            judges.retain(|known| known.url != judge.url);
+            judges.push(judge);
+            working += 1;
+            drop(judges);
+            JUDGES_FOUND.notify_waiters();
         } else {
-            if expected_types.contains(&judge.scheme) {
+            if expected_schemes.contains(&judge.scheme) {
+                JUDGES.remove_if_mut(&judge.scheme, |_, judges| {
+                    judges.retain(|known| known.url != judge.url);
+                    judges.is_empty()
+                });
                 no_judges.insert(judge.scheme.clone());
             }
 
-            if judge.scheme == "HTTP" {
-                for protocol in vec_of_strings!["HTTP", "CONNECT:80", "SOCKS4", "SOCKS5"] {
-                    disable_protocols.insert(protocol);
-                }
-            } else if judge.scheme == "SMTP" {
-                let protocol = String::from("CONNECT:25");
-                disable_protocols.insert(protocol);
-            } else {
-                let protocol = String::from("HTTPS");
+            for protocol in protocols_for_scheme(&judge.scheme) {
                 disable_protocols.insert(protocol);
             }
         }
     }
 
-    for types in expected_types.iter() {
-        if JUDGES.contains_key(types) {
-            ENABLE_PROTOCOLS.lock().extend(match types.as_str() {
-                "HTTP" => vec_of_strings!["HTTP", "CONNECT:80", "SOCKS4", "SOCKS5"],
-                "HTTPS" => vec_of_strings!["HTTPS"],
-                "SMTP" => vec_of_strings!["CONNECT:25"],
-                _ => vec![],
-            })
+    for scheme in expected_schemes.iter() {
+        let working = JUDGES.get(scheme).map_or(0, |judges| judges.len());
+        metrics::JUDGES
+            .with_label_values(&[scheme])
+            .set(working as i64);
+        if JUDGES.contains_key(scheme) {
+            ENABLE_PROTOCOLS.lock().extend(protocols_for_scheme(scheme))
         }
     }
     let no_judges: Vec<String> = no_judges
@@ -125,47 +176,53 @@ pub async fn check_judges(ssl: bool, ext_ip: String, mut expected_types: Vec<Str
                 .collect::<Vec<String>>(),
         );
     }
-    if working == 0 || expected_types.into_iter().all(|f| no_judges.contains(&f)) {
-        log::error!("No judges found!");
-        while *DOWNLOADING.lock() {
-            continue;
-        }
-        exit(0);
+    JUDGES_CHECKED.store(true, Ordering::SeqCst);
+    JUDGES_FOUND.notify_waiters();
+    if working == 0 || expected_schemes.into_iter().all(|f| no_judges.contains(&f)) {
+        return Err(ProxyError::NoJudges);
     }
     log::info!("{} judges added, Runtime {:?}", working, stime.elapsed());
+    Ok(())
 }
 
+/// Checks proxies against the judges found by [`check_judges`], which must run
+/// first. A clone shares the judges and can be moved to another task.
 #[derive(Clone, Debug)]
 pub struct Checker {
+    /// Whether certificates of HTTPS judges are verified.
     pub verify_ssl: bool,
+    /// Seconds each connection, send and receive may take.
     pub timeout: i32,
+    /// The maximum number of attempts per protocol.
     pub max_tries: i32,
+    /// HTTP method of judge requests, `GET` or `POST`.
     pub method: String,
 
+    /// Whether judge requests carry a `Referer` header.
     pub support_referer: bool,
+    /// Whether judge requests carry a `Cookie` header.
     pub support_cookie: bool,
 
-    pub expected_types: Vec<String>,
-    pub expected_levels: Vec<String>,
+    /// Protocols to check, the others are skipped.
+    pub expected_types: Vec<ProxyProtocol>,
+    /// Levels of anonymity an HTTP proxy must have. Empty allows any.
+    pub expected_levels: Vec<AnonymityLevel>,
+    /// ISO codes of the countries a proxy must be located in. Empty allows any.
     pub expected_countries: Vec<String>,
 
+    /// External ip address of this host, a proxy revealing it is transparent.
     pub ext_ip: String,
     ip_re: Regex,
 }
 
 impl Checker {
+    /// Checks every expected protocol of `proxy`, recording those that work in
+    /// `proxy.types`. Returns whether any of them works.
     pub async fn check_proxy(&mut self, proxy: &mut Proxy) -> bool {
-        let expected_types = vec_of_strings![
-            "CONNECT:80",
-            "CONNECT:25",
-            "SOCKS5",
-            "SOCKS4",
-            "HTTPS",
-            "HTTP"
-        ]; // proxy.expected_types.clone();
+        let protocols = NEGOTIATORS.read().protocols();
 
         let mut result = vec![];
-        for proto in &expected_types {
+        for proto in &protocols {
             if self.expected_types.contains(proto)
                 && ENABLE_PROTOCOLS.lock().contains(proto)
                 && (self.expected_countries.is_empty()
@@ -178,11 +235,17 @@ impl Checker {
                         break;
                     }
                 }
-                if proto == "HTTP" && is_working && !self.expected_levels.is_empty() {
+                if *proto == ProxyProtocol::Http && is_working && !self.expected_levels.is_empty() {
                     is_working = proxy.types.iter().any(|(_, level)| {
-                        level.is_some() && self.expected_levels.contains(&level.clone().unwrap())
+                        level.is_some_and(|level| self.expected_levels.contains(&level))
                     });
                 }
+                CHECKS
+                    .with_label_values(&[
+                        proto.as_str(),
+                        if is_working { "passed" } else { "failed" },
+                    ])
+                    .inc();
                 result.push(is_working)
             }
         }
@@ -191,116 +254,80 @@ impl Checker {
         proxy.is_working
     }
 
-    pub async fn check_proto(&mut self, proxy: &mut Proxy, proto: &String) -> bool {
-        proxy.negotiator_proto = proto.to_string();
+    /// Checks a single protocol of `proxy` once, through a judge of its scheme.
+    pub async fn check_proto(&mut self, proxy: &mut Proxy, proto: &ProxyProtocol) -> bool {
+        let negotiator = match get_negotiator(proto) {
+            Some(negotiator) => negotiator,
+            None => return false,
+        };
+
+        proxy.negotiator_proto = *proto;
         let mut is_working = false;
-        if let Some(judge) = self.get_judge(proto) {
-            proxy.log(format!("Selected judge: {}", judge).as_str(), None, None);
+        if let Ok(judge) = self.get_judge(negotiator.judge_scheme()).await {
+            proxy.log(format!("Selected judge: {}", judge).as_str(), None);
 
-            if proto != "HTTPS" && !proxy.connect().await {
+            if negotiator.need_connection() && !proxy.connect().await {
                 proxy.close().await;
                 return false;
             }
 
-            let (negotiate_success, use_full_path, check_anon_lvl) =
-                self.negotiate(proxy, &judge, proto).await;
-            if !negotiate_success {
+            if !negotiator.negotiate(proxy, &judge).await {
                 proxy.close().await;
                 return false;
             }
 
-            if proto == "CONNECT:25" {
-                proxy.types.push((proto.to_string(), None));
+            if !negotiator.need_request() {
+                proxy.types.push((*proto, None));
                 return true;
             }
 
             let path = judge.url.path().to_string();
-            let (raw_request, headers, rv) =
-                self.build_raw_request(&judge.host, &path, use_full_path, None);
+            let credentials = if negotiator.use_full_path() {
+                proxy.credentials.clone()
+            } else {
+                None
+            };
+            let (raw_request, headers, rv) = self.build_raw_request(
+                &judge.host,
+                &path,
+                negotiator.use_full_path(),
+                credentials.as_ref(),
+                None,
+            );
 
             proxy.send(raw_request.as_bytes()).await;
             if let Some(data) = proxy.recv_all().await {
-                proxy.log("Request: success", None, None);
+                proxy.log("Request: success", None);
                 let mut anonimity_lvl = None;
                 let response = ResponseParser::parse(data.as_slice());
+                if response.status_code == Some(407) {
+                    proxy.log_error(ProxyError::AuthRequired, None);
+                }
 
                 //log::warn!("=====\n{raw_request}\n{0}", response.raw);
 
                 if self.get_response_status(&response, headers, rv) {
-                    if check_anon_lvl {
+                    if negotiator.check_anon_lvl() {
                         anonimity_lvl = Some(self.get_anonimity_level(&response, &judge.marks));
                     }
 
                     is_working = true;
-                    proxy.types.push((proto.to_string(), anonimity_lvl));
+                    proxy.types.push((*proto, anonimity_lvl));
                 }
                 proxy.close().await;
             } else {
-                proxy.log("Request: failed", None, Some("request_failed".to_string()));
+                proxy.log_error(ProxyError::RequestFailed, None);
             }
         }
 
         is_working
     }
 
-    async fn negotiate(
-        &self,
-        proxy: &mut Proxy,
-        judge: &Judge,
-        proto: &String,
-    ) -> (bool, bool, bool) {
-        if proto == "CONNECT:25" {
-            let negotiator = Connect25Negotiator::default();
-            (
-                negotiator.negotiate(proxy, judge).await,
-                negotiator.use_full_path,
-                negotiator.check_anon_lvl,
-            )
-        } else if proto == "CONNECT:80" {
-            let negotiator = Connect80Negotiator::default();
-            (
-                negotiator.negotiate(proxy, judge).await,
-                negotiator.use_full_path,
-                negotiator.check_anon_lvl,
-            )
-        } else if proto == "SOCKS5" {
-            let negotiator = Socks5Negotiator::default();
-            (
-                negotiator.negotiate(proxy).await,
-                negotiator.use_full_path,
-                negotiator.check_anon_lvl,
-            )
-        } else if proto == "SOCKS4" {
-            let negotiator = Socks4Negotiator::default();
-            (
-                negotiator.negotiate(proxy).await,
-                negotiator.use_full_path,
-                negotiator.check_anon_lvl,
-            )
-        } else if proto == "HTTPS" {
-            let negotiator = HttpsNegotiator::default();
-            (
-                negotiator.negotiate(proxy, judge).await,
-                negotiator.use_full_path,
-                negotiator.check_anon_lvl,
-            )
-        } else if proto == "HTTP" {
-            let negotiator = HttpNegotiator::default();
-            (
-                negotiator.negotiate().await,
-                negotiator.use_full_path,
-                negotiator.check_anon_lvl,
-            )
-        } else {
-            (false, false, false)
-        }
-    }
-
     fn get_anonimity_level(
         &self,
         response: &ResponseParser,
         marks: &BTreeMap<String, usize>,
-    ) -> String {
+    ) -> AnonymityLevel {
         let content = response.body.to_lowercase();
         let mut via = false;
         if let Some(via_m) = marks.get("via") {
@@ -312,18 +339,16 @@ impl Checker {
             }
         }
 
-        let all_ips: Vec<String> = self
-            .ip_re
-            .find_iter(&content)
-            .map(|f| f.as_str().to_string())
+        let all_ips: Vec<String> = find_ips(&self.ip_re, &content)
+            .map(|ip| ip.to_string())
             .collect();
 
-        if all_ips.contains(&self.ext_ip) {
-            "Transparent".to_string()
+        if all_ips.contains(&self.ext_ip.to_lowercase()) {
+            AnonymityLevel::Transparent
         } else if via {
-            "Anonymous".to_string()
+            AnonymityLevel::Anonymous
         } else {
-            "High".to_string()
+            AnonymityLevel::High
         }
     }
 
@@ -353,7 +378,7 @@ impl Checker {
         } else {
             true
         };
-        let some_ip = self.ip_re.find(&response_raw).is_some();
+        let some_ip = find_ips(&self.ip_re, &response_raw).next().is_some();
         let is_ok = response.status_code.unwrap_or(0) == 200;
 
         is_ok && version_is_correct && some_ip && support_referer && support_cookie
@@ -364,6 +389,7 @@ impl Checker {
         host: &String,
         path: &String,
         use_full_path: bool,
+        credentials: Option<&Credentials>,
         data: Option<String>,
     ) -> (String, BTreeMap<String, String>, String) {
         let mut request = if use_full_path {
@@ -376,6 +402,9 @@ impl Checker {
         let data = data.unwrap_or("".to_string());
         headers.insert("Host".to_string(), host.to_string());
         headers.insert("Connection".to_string(), "close".to_string());
+        if let Some(credentials) = credentials {
+            headers.insert("Proxy-Authorization".to_string(), credentials.basic_auth());
+        }
         headers.insert("Content-Length".to_string(), data.len().to_string());
         if self.method == "POST" {
             headers.insert(
@@ -392,39 +421,35 @@ impl Checker {
         (request, headers, rv)
     }
 
-    fn get_judge(&mut self, proto: &str) -> Option<Judge> {
-        let mut scheme = "HTTP".to_string();
-        if proto.eq("HTTPS") {
-            scheme = "HTTPS".to_string();
-        } else if proto.eq("CONNECT:25") {
-            scheme = "SMTP".to_string();
-        }
-
-        let t = time::Instant::now();
-        while !JUDGES.contains_key(&scheme) {
-            if t.elapsed() >= Duration::from_secs(15) {
-                log::error!("Timeout error: no judges found");
-                while *DOWNLOADING.lock() {
-                    continue;
+    /// Picks a working judge for `scheme`, waiting for one while the judges
+    /// are being checked.
+    async fn get_judge(&mut self, scheme: &str) -> Result<Judge, ProxyError> {
+        let _ = time::timeout(Duration::from_secs(15), async {
+            loop {
+                let mut added = pin!(JUDGES_FOUND.notified());
+                added.as_mut().enable();
+                if JUDGES.contains_key(scheme) || JUDGES_CHECKED.load(Ordering::SeqCst) {
+                    return;
                 }
-                exit(0)
-            }
-        }
-
-        if let Some(v) = JUDGES.get_mut(&scheme) {
-            if let Some(judge) = v.choose(&mut thread_rng()) {
-                return Some(judge.clone());
+                added.await;
             }
-        }
+        })
+        .await;
 
-        None
+        JUDGES
+            .get(scheme)
+            .and_then(|judges| judges.choose(&mut thread_rng()).cloned())
+            .ok_or(ProxyError::NoJudges)
     }
 }
 
 impl Checker {
-    pub async fn new() -> Self {
+    /// Checker of every protocol, with 3 tries and a 5 seconds timeout.
+    ///
+    /// Fails when the external ip address can not be retrieved.
+    pub async fn new() -> Result<Self, ProxyError> {
         let resolver = Resolver::new();
-        Checker {
+        Ok(Checker {
             verify_ssl: false,
             timeout: 5,
             max_tries: 3,
@@ -434,8 +459,30 @@ impl Checker {
             expected_types: vec![],
             expected_countries: vec![],
             expected_levels: vec![],
-            ip_re: Regex::new(r#"\d+\.\d+\.\d+\.\d+"#).unwrap(),
-            ext_ip: resolver.get_real_ext_ip().await,
-        }
+            ip_re: Regex::new(IP_PATTERN).unwrap(),
+            ext_ip: resolver.get_real_ext_ip().await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(text: &str) -> Vec<String> {
+        let ip_re = Regex::new(IP_PATTERN).unwrap();
+        find_ips(&ip_re, text).map(String::from).collect()
+    }
+
+    #[test]
+    fn date_header_is_not_an_ip() {
+        let response = "http/1.1 200 ok\r\ndate: sat, 18 oct 2026 07:00:00 gmt\r\n\r\nhello";
+        assert!(ips(response).is_empty());
+    }
+
+    #[test]
+    fn finds_ipv4_and_ipv6() {
+        let response = "date: sat, 18 oct 2026 07:00:00 gmt\r\n\r\n1.2.3.4 2001:db8::1";
+        assert_eq!(ips(response), vec!["1.2.3.4", "2001:db8::1"]);
     }
 }
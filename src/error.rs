@@ -0,0 +1,105 @@
+//! Errors met while checking proxies.
+
+use std::io::{self, ErrorKind};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ProxyError {
+    #[error("Connection timeout")]
+    ConnectTimeout,
+    #[error("Connection refused")]
+    ConnectRefused,
+    #[error("Connection error: {0}")]
+    Connect(io::Error),
+    #[error("Sending error: {0}")]
+    Send(io::Error),
+    #[error("Received timeout")]
+    RecvTimeout,
+    #[error("Failed to receive: {0}")]
+    Recv(io::Error),
+    #[error("Failed to close connection: {0}")]
+    Close(io::Error),
+    #[error("Bad HTTP status: {0}")]
+    BadStatus(u16),
+    #[error("Proxy authentication required")]
+    AuthRequired,
+    #[error("Proxy authentication rejected")]
+    AuthFailed,
+    #[error("SSL: handshake failed: {0}")]
+    TlsHandshake(String),
+    #[error("Invalid SOCKS reply: {0}")]
+    InvalidSocksReply(&'static str),
+    #[error("Request rejected: {0:#04x}")]
+    RequestRejected(u8),
+    #[error("Invalid input: {0}")]
+    InvalidInput(&'static str),
+    #[error("Handshake timeout")]
+    HandshakeTimeout,
+    #[error("Request failed")]
+    RequestFailed,
+    #[error("No judges found")]
+    NoJudges,
+    #[error("Failed to retrieve the external ip address")]
+    NoExternalIp,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl ProxyError {
+    /// Stable name of the error, used as key of `Proxy::error_stat` and as
+    /// metrics label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProxyError::ConnectTimeout => "connect_timeout",
+            ProxyError::ConnectRefused => "connect_refused",
+            ProxyError::Connect(_) => "connection_error",
+            ProxyError::Send(_) => "send_error",
+            ProxyError::RecvTimeout => "recv_timeout",
+            ProxyError::Recv(_) => "recv_error",
+            ProxyError::Close(_) => "close_error",
+            ProxyError::BadStatus(_) => "bad_status",
+            ProxyError::AuthRequired => "auth_required",
+            ProxyError::AuthFailed => "auth_failed",
+            ProxyError::TlsHandshake(_) => "tls_handshake",
+            ProxyError::InvalidSocksReply(_) => "invalid_socks_reply",
+            ProxyError::RequestRejected(_) => "request_rejected",
+            ProxyError::InvalidInput(_) => "invalid_input",
+            ProxyError::HandshakeTimeout => "handshake_timeout",
+            ProxyError::RequestFailed => "request_failed",
+            ProxyError::NoJudges => "no_judges",
+            ProxyError::NoExternalIp => "no_external_ip",
+            ProxyError::Io(_) => "io_error",
+        }
+    }
+
+    /// Error of a failed tcp connection.
+    pub fn connect(err: io::Error) -> Self {
+        match err.kind() {
+            ErrorKind::ConnectionRefused => ProxyError::ConnectRefused,
+            ErrorKind::TimedOut => ProxyError::ConnectTimeout,
+            _ => ProxyError::Connect(err),
+        }
+    }
+}
+
+impl From<ProxyError> for io::Error {
+    fn from(err: ProxyError) -> Self {
+        if let ProxyError::Io(err) = err {
+            return err;
+        }
+        let kind = match &err {
+            ProxyError::ConnectTimeout | ProxyError::RecvTimeout | ProxyError::HandshakeTimeout => {
+                ErrorKind::TimedOut
+            }
+            ProxyError::ConnectRefused
+            | ProxyError::BadStatus(_)
+            | ProxyError::RequestRejected(_) => ErrorKind::ConnectionRefused,
+            ProxyError::AuthRequired | ProxyError::AuthFailed => ErrorKind::PermissionDenied,
+            ProxyError::InvalidSocksReply(_) => ErrorKind::InvalidData,
+            ProxyError::InvalidInput(_) => ErrorKind::InvalidInput,
+            _ => ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
@@ -0,0 +1,377 @@
+use std::{
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::Stream;
+use tokio::{
+    sync::{mpsc, watch, Semaphore},
+    task::{self, JoinHandle, JoinSet},
+    time,
+};
+
+use crate::{
+    checker::{self, Checker},
+    error::ProxyError,
+    providers::{self, Candidates},
+    proxy::Proxy,
+    recheck::Change,
+    store::ProxyStore,
+    types::{AnonymityLevel, ProxyProtocol},
+    utils::serializer::ProxyData,
+};
+
+/// A [`Proxy`] that passed the checks of a [`Finder`].
+pub type CheckedProxy = Proxy;
+
+/// Finds proxies and checks them against the configured requirements.
+///
+/// ```no_run
+/// use futures_util::StreamExt;
+/// use proxy_rs::{finder::Finder, AnonymityLevel, ProxyProtocol};
+///
+/// # async fn run() -> Result<(), proxy_rs::ProxyError> {
+/// let mut proxies = Finder::builder()
+///     .types(vec![ProxyProtocol::Http, ProxyProtocol::Https])
+///     .levels(vec![AnonymityLevel::High])
+///     .limit(10)
+///     .build()
+///     .stream()
+///     .await?;
+///
+/// while let Some(proxy) = proxies.next().await {
+///     println!("{}", proxy);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Finder {
+    types: Vec<ProxyProtocol>,
+    countries: Vec<String>,
+    levels: Vec<AnonymityLevel>,
+    files: Vec<PathBuf>,
+    limit: usize,
+    max_conn: usize,
+    max_tries: i32,
+    timeout: i32,
+    support_cookies: bool,
+    support_referer: bool,
+    verify_ssl: bool,
+    store: Option<Arc<ProxyStore>>,
+}
+
+impl Finder {
+    pub fn builder() -> FinderBuilder {
+        FinderBuilder::default()
+    }
+
+    /// Starts finding proxies in the background and returns the checked ones.
+    ///
+    /// Fails when proxies can not be checked, see [`Checker::new`] and
+    /// [`checker::check_judges`]. Must be called from within a tokio runtime.
+    /// Dropping the stream stops all background tasks.
+    pub async fn stream(self) -> Result<impl Stream<Item = CheckedProxy>, ProxyError> {
+        let (tx, rx) = mpsc::channel(50);
+        let limit = self.limit;
+        // set once all the input was read, never when scraping providers
+        let (loaded_tx, mut loaded) = watch::channel(false);
+        let mut tasks = vec![];
+        let candidates = Arc::new(Candidates::new());
+
+        if self.files.is_empty() {
+            let store = self.store.clone();
+            let candidates = Arc::clone(&candidates);
+            tasks.push(task::spawn(async move {
+                let _loaded = loaded_tx;
+                if let Some(store) = store {
+                    providers::handle_store_input(&store, &candidates).await;
+                }
+                let dur = Duration::from_secs(60);
+                loop {
+                    providers::run_all_providers(3, &candidates).await;
+                    log::debug!("Next cycle starts at {:?}", dur);
+                    time::sleep(dur).await;
+                }
+            }));
+        } else {
+            let files = self.files.clone();
+            let store = self.store.clone();
+            let candidates = Arc::clone(&candidates);
+            tasks.push(task::spawn(async move {
+                if let Some(store) = store {
+                    providers::handle_store_input(&store, &candidates).await;
+                }
+                providers::handle_file_input(files, &candidates).await;
+                loaded_tx.send_replace(true);
+            }));
+        }
+
+        let checker = match self.checker().await {
+            Ok(checker) => checker,
+            Err(err) => {
+                tasks.iter().for_each(JoinHandle::abort);
+                return Err(err);
+            }
+        };
+        tasks.push(task::spawn(async move {
+            let sem = Arc::new(Semaphore::new(self.max_conn));
+            // aborted when this task is, so that no check outlives the stream
+            let mut checks = JoinSet::new();
+            loop {
+                while let Ok(mut proxy) = candidates.proxies.pop() {
+                    let permit = Arc::clone(&sem).acquire_owned().await;
+                    let mut checker = checker.clone();
+                    let tx = tx.clone();
+                    let store = self.store.clone();
+
+                    checks.spawn(async move {
+                        let _ = permit;
+                        let is_working = checker.check_proxy(&mut proxy).await;
+                        record(store, &proxy).await;
+                        if is_working {
+                            let _ = tx.send(proxy).await;
+                        }
+                    });
+                }
+                if *loaded.borrow() && candidates.proxies.is_empty() {
+                    while checks.join_next().await.is_some() {}
+                    return;
+                }
+                tokio::select! {
+                    _ = candidates.proxies.wait_for_item() => {}
+                    _ = loaded.changed() => {}
+                    Some(_) = checks.join_next() => {}
+                    _ = tx.closed() => return,
+                }
+            }
+        }));
+
+        Ok(FinderStream {
+            rx,
+            tasks,
+            remaining: if limit == 0 { None } else { Some(limit) },
+        })
+    }
+}
+
+impl Finder {
+    /// Checks `previous` proxies again and reports how each of them changed.
+    ///
+    /// Fails like [`Finder::stream`]. Must be called from within a tokio
+    /// runtime. Dropping the stream stops all checks.
+    pub async fn recheck(
+        self,
+        previous: Vec<ProxyData>,
+    ) -> Result<impl Stream<Item = Change>, ProxyError> {
+        let (tx, rx) = mpsc::channel(50);
+        let checker = self.checker().await?;
+        let task = task::spawn(async move {
+            let sem = Arc::new(Semaphore::new(self.max_conn));
+            let mut checks = JoinSet::new();
+            for mut previous in previous {
+                // protocols that are not checked again can not have changed
+                previous
+                    .types
+                    .retain(|proxy_type| self.types.contains(&proxy_type.proxy_type));
+                let permit = Arc::clone(&sem).acquire_owned().await;
+                let mut checker = checker.clone();
+                let tx = tx.clone();
+                let store = self.store.clone();
+                let types = self.types.clone();
+
+                checks.spawn(async move {
+                    let _ = permit;
+                    let host = previous.hostname.as_ref().unwrap_or(&previous.host);
+                    let current = match Proxy::create(host, previous.port, types).await {
+                        Some(mut proxy) => {
+                            proxy.credentials = previous.credentials.clone();
+                            if !checker.check_proxy(&mut proxy).await {
+                                proxy.types.clear();
+                            }
+                            record(store, &proxy).await;
+                            proxy.as_data()
+                        }
+                        None => ProxyData {
+                            types: vec![],
+                            ..previous.clone()
+                        },
+                    };
+                    let _ = tx.send(Change::new(previous, current)).await;
+                });
+            }
+            while checks.join_next().await.is_some() {}
+        });
+
+        Ok(FinderStream {
+            rx,
+            tasks: vec![task],
+            remaining: None,
+        })
+    }
+
+    async fn checker(&self) -> Result<Checker, ProxyError> {
+        let mut checker = Checker::new().await?;
+        checker.max_tries = self.max_tries;
+        checker.timeout = self.timeout;
+        checker.verify_ssl = self.verify_ssl;
+        checker.support_cookie = self.support_cookies;
+        checker.support_referer = self.support_referer;
+        checker.expected_types = self.types.clone();
+        checker.expected_levels = self.levels.clone();
+        checker.expected_countries = self.countries.clone();
+
+        let ext_ip = checker.ext_ip.clone();
+        checker::check_judges(self.verify_ssl, ext_ip, self.types.clone()).await?;
+        Ok(checker)
+    }
+}
+
+/// Records a check of `proxy` in `store`, if any.
+async fn record(store: Option<Arc<ProxyStore>>, proxy: &Proxy) {
+    // proxies skipped by the filters or for lack of judges leave no logs
+    if let (Some(store), false) = (store, proxy.logs.is_empty()) {
+        if let Err(err) = store.record_async(proxy).await {
+            log::error!("Failed to record {}: {}", proxy.as_text(), err);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FinderBuilder {
+    finder: Finder,
+}
+
+impl FinderBuilder {
+    /// Protocols to check. By default, all built-in protocols.
+    pub fn types(mut self, types: Vec<ProxyProtocol>) -> Self {
+        self.finder.types = types;
+        self
+    }
+
+    /// ISO country codes where proxies should be located. By default, any country.
+    pub fn countries(mut self, countries: Vec<String>) -> Self {
+        self.finder.countries = countries;
+        self
+    }
+
+    /// Levels of anonymity (for HTTP only). By default, any level.
+    pub fn levels(mut self, levels: Vec<AnonymityLevel>) -> Self {
+        self.finder.levels = levels;
+        self
+    }
+
+    /// Files with proxies, used instead of providers when not empty.
+    pub fn files(mut self, files: Vec<PathBuf>) -> Self {
+        self.finder.files = files;
+        self
+    }
+
+    /// The maximum number of working proxies, `0` means unlimited.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.finder.limit = limit;
+        self
+    }
+
+    /// The maximum number of concurrent checks.
+    pub fn max_conn(mut self, max_conn: usize) -> Self {
+        self.finder.max_conn = max_conn;
+        self
+    }
+
+    /// The maximum number of attempts to check a proxy.
+    pub fn max_tries(mut self, max_tries: i32) -> Self {
+        self.finder.max_tries = max_tries;
+        self
+    }
+
+    /// Time in seconds before giving up.
+    pub fn timeout(mut self, timeout: i32) -> Self {
+        self.finder.timeout = timeout;
+        self
+    }
+
+    pub fn support_cookies(mut self, support_cookies: bool) -> Self {
+        self.finder.support_cookies = support_cookies;
+        self
+    }
+
+    pub fn support_referer(mut self, support_referer: bool) -> Self {
+        self.finder.support_referer = support_referer;
+        self
+    }
+
+    pub fn verify_ssl(mut self, verify_ssl: bool) -> Self {
+        self.finder.verify_ssl = verify_ssl;
+        self
+    }
+
+    /// Database recording every check, proxies that passed their last check
+    /// are checked first.
+    pub fn store(mut self, store: Arc<ProxyStore>) -> Self {
+        self.finder.store = Some(store);
+        self
+    }
+
+    pub fn build(mut self) -> Finder {
+        if self.finder.types.is_empty() {
+            self.finder.types = ProxyProtocol::BUILTIN.to_vec();
+        }
+        self.finder
+    }
+}
+
+impl Default for FinderBuilder {
+    fn default() -> Self {
+        Self {
+            finder: Finder {
+                types: vec![],
+                countries: vec![],
+                levels: vec![],
+                files: vec![],
+                limit: 0,
+                max_conn: 2000,
+                max_tries: 1,
+                timeout: 8,
+                support_cookies: false,
+                support_referer: false,
+                verify_ssl: false,
+                store: None,
+            },
+        }
+    }
+}
+
+struct FinderStream<T> {
+    rx: mpsc::Receiver<T>,
+    tasks: Vec<JoinHandle<()>>,
+    remaining: Option<usize>,
+}
+
+impl<T> Stream for FinderStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.remaining == Some(0) {
+            return Poll::Ready(None);
+        }
+        let poll = self.rx.poll_recv(cx);
+        if let Poll::Ready(Some(_)) = poll {
+            if let Some(remaining) = self.remaining.as_mut() {
+                *remaining -= 1;
+            }
+        }
+        poll
+    }
+}
+
+impl<T> Drop for FinderStream<T> {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
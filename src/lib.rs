@@ -0,0 +1,54 @@
+//! Fast Rust-based proxy finder, checker and server.
+//!
+//! The [`proxy-rs`](https://github.com/zevtyardt/proxy.rs) binary is a thin
+//! client of this library. The main building blocks are:
+//!
+//! - [`ProviderTask`] scrapes proxies from a [`Provider`] source.
+//! - [`Proxy`] holds a single proxy, its connection and its statistics.
+//! - [`Checker`] verifies the protocols and anonymity level of a [`Proxy`].
+//! - [`Resolver`] resolves hosts and looks up geo information.
+//! - [`Server`] runs a local proxy server on top of checked proxies.
+//! - [`Finder`] ties the above together and streams checked proxies.
+//!
+//! ```no_run
+//! use proxy_rs::{checker, Checker, Proxy, ProxyProtocol};
+//!
+//! # async fn run() -> Result<(), proxy_rs::ProxyError> {
+//! let mut checker = Checker::new().await?;
+//! checker.expected_types = vec![ProxyProtocol::Http];
+//!
+//! let ext_ip = checker.ext_ip.clone();
+//! checker::check_judges(false, ext_ip, vec![ProxyProtocol::Http]).await?;
+//!
+//! if let Some(mut proxy) = Proxy::create("127.0.0.1", 8080, vec![]).await {
+//!     if checker.check_proxy(&mut proxy).await {
+//!         println!("{}", proxy);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod checker;
+pub mod error;
+pub mod finder;
+pub mod judge;
+pub mod metrics;
+pub mod negotiators;
+pub mod providers;
+pub mod proxy;
+pub mod recheck;
+pub mod resolver;
+pub mod server;
+pub mod store;
+pub mod types;
+pub mod utils;
+
+pub use checker::Checker;
+pub use error::ProxyError;
+pub use finder::{CheckedProxy, Finder};
+pub use providers::base_provider::{Provider, ProviderTask};
+pub use proxy::Proxy;
+pub use resolver::Resolver;
+pub use server::Server;
+pub use types::{AnonymityLevel, ProxyProtocol};
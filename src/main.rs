@@ -1,123 +1,112 @@
-#![allow(dead_code)]
-//#![allow(unused_variables)]
-//#![allow(unused_imports)]
-//#![allow(unreachable_code)]
-
-use argument::GrabArgs;
-use checker::Checker;
+use argument::{GrabArgs, RecheckArgs};
 use clap::Parser;
-use lazy_static::lazy_static;
-use parking_lot::Mutex;
-use proxy::Proxy;
-use regex::Regex;
-use server::{proxy_pool::LIVE_PROXIES, Server};
+use futures_util::{stream, Stream, StreamExt};
+use proxy_rs::{
+    finder::Finder,
+    metrics,
+    providers::{self, Candidates},
+    recheck::ChangeKind,
+    server::{client::load_users, proxy_pool::LIVE_PROXIES},
+    store::ProxyStore,
+    utils::{geolite_database::wait_for_download, serializer::ProxyData, update::check_version},
+    Proxy, ProxyError, Server,
+};
 use simple_logger::SimpleLogger;
-use std::{path::PathBuf, pin::Pin, sync::Arc, time::Duration};
+use std::{fs, pin::Pin, sync::Arc, time::Duration};
 use tokio::{
     fs::File,
-    io::{stdout, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    io::{stdout, AsyncWrite, AsyncWriteExt},
     runtime,
-    sync::{
-        mpsc::{self, Sender},
-        Semaphore,
-    },
+    sync::mpsc::{self, Sender},
     task, time,
 };
 
-use crate::{
-    argument::{Cli, Commands},
-    providers::PROXIES,
-    utils::update::check_version,
-};
+use crate::argument::{Cli, Commands};
 
 mod argument;
-mod checker;
-mod judge;
-mod negotiators;
-mod providers;
-mod proxy;
-mod resolver;
-mod server;
-mod utils;
-
-lazy_static! {
-    static ref STOP_FIND_LOOP: Arc<Mutex<bool>> = Arc::new(Mutex::new(false));
-}
-
-struct ProxiesIter;
-impl Iterator for ProxiesIter {
-    type Item = Proxy; //(String, u16, Vec<String>);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Ok(proxy) = PROXIES.pop() {
-            return Some(proxy);
-        }
-        None
-    }
-}
 
-async fn handle_grab_command(args: GrabArgs, tx: Sender<Option<Proxy>>) {
+async fn handle_grab_command(args: GrabArgs, candidates: Arc<Candidates>, tx: Sender<Proxy>) {
     let expected_countries = args.countries;
 
     loop {
-        let proxies = ProxiesIter {};
-        for proxy in proxies {
-            //if let Some(proxy) = proxy::Proxy::create(host.as_str(), port, expected_types).await {
-            if !expected_countries.is_empty() && !expected_countries.contains(&proxy.geo.iso_code) {
-                continue;
-            }
-            if tx.send(Some(proxy)).await.is_err() {
-                return;
-            }
-            //}
+        let proxy = candidates.proxies.pop_wait().await;
+        if !expected_countries.is_empty() && !expected_countries.contains(&proxy.geo.iso_code) {
+            continue;
+        }
+        if tx.send(proxy).await.is_err() {
+            return;
         }
     }
 }
 
-async fn handle_find_command(checker: Checker, max_conn: usize, tx: Sender<Option<Proxy>>) {
-    while !*STOP_FIND_LOOP.lock() {
-        let sem = Arc::new(Semaphore::new(max_conn));
-        let proxies = ProxiesIter {};
-        for mut proxy in proxies {
-            if *STOP_FIND_LOOP.lock() {
-                tx.send(None).await.unwrap();
-                return;
-            }
-            let permit = Arc::clone(&sem).acquire_owned().await;
-            let mut checker = checker.clone();
-            let tx = tx.clone();
+/// Reports an error that prevents checking proxies and exits.
+async fn exit_on_error(err: ProxyError) -> ! {
+    log::error!("{}", err);
+    wait_for_download().await;
+    std::process::exit(1);
+}
 
-            task::spawn(async move {
-                let _ = permit;
-                if checker.check_proxy(&mut proxy).await {
-                    tx.send(Some(proxy)).await.unwrap();
-                }
-            });
+async fn handle_recheck_command(
+    args: RecheckArgs,
+    max_conn: usize,
+    timeout: i32,
+    store: Option<Arc<ProxyStore>>,
+) {
+    let previous: Vec<ProxyData> = match fs::read_to_string(&args.file)
+        .map_err(|err| err.to_string())
+        .and_then(|data| serde_json::from_str(&data).map_err(|err| err.to_string()))
+    {
+        Ok(previous) => previous,
+        Err(err) => {
+            eprintln!("Failed to read {}: {}", args.file.display(), err);
+            std::process::exit(1);
         }
+    };
+
+    let mut finder = Finder::builder()
+        .types(args.types)
+        .max_conn(max_conn)
+        .max_tries(args.max_tries as i32)
+        .timeout(timeout);
+    if let Some(store) = store {
+        finder = finder.store(store);
     }
-}
+    let mut changes = match finder.build().recheck(previous).await {
+        Ok(changes) => changes,
+        Err(err) => exit_on_error(err).await,
+    };
 
-async fn handle_file_input(files: Vec<PathBuf>) {
-    for file in files {
-        match File::open(&file).await {
-            Ok(file) => {
-                let ip_port = Regex::new(r#"(?P<ip>(?:\d+\.?){4}):(?P<port>\d+)"#).unwrap();
-                let buffer = BufReader::new(file);
-                let mut lines = buffer.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    if let Some(cap) = ip_port.captures(&line) {
-                        let ip = cap.get(1).unwrap().as_str();
-                        let port = cap.get(2).unwrap().as_str();
+    let mut output: Pin<Box<dyn AsyncWrite>> = if let Some(path) = args.outfile {
+        Box::pin(File::create(path).await.unwrap())
+    } else {
+        Box::pin(stdout())
+    };
+    let mut rechecked = vec![];
+    let mut open_list = false;
+    while let Some(change) = changes.next().await {
+        if args.all || change.kind != ChangeKind::Unchanged {
+            let msg = if args.format == "json" {
+                let separator = if open_list { ",\n" } else { "[" };
+                open_list = true;
+                format!("{}{}", separator, change.as_json())
+            } else {
+                format!("{}\n", change)
+            };
+            output.write_all(msg.as_bytes()).await.unwrap();
+            output.flush().await.unwrap();
+        }
+        rechecked.push(change.current);
+    }
+    if args.format == "json" {
+        let close = if open_list { "]\n" } else { "[]\n" };
+        output.write_all(close.as_bytes()).await.unwrap();
+    }
+    output.flush().await.unwrap();
 
-                        if let Ok(port) = port.parse::<u16>() {
-                            if let Some(proxy) = Proxy::create(ip, port, vec![]).await {
-                                PROXIES.push(proxy).unwrap()
-                            }
-                        };
-                    }
-                }
-            }
-            Err(e) => log::error!("{}: {:?}", e, file),
+    if let Some(path) = args.save {
+        if let Err(err) = fs::write(&path, serde_json::to_string(&rechecked).unwrap()) {
+            eprintln!("Failed to write {}: {}", path.display(), err);
+            std::process::exit(1);
         }
     }
 }
@@ -145,172 +134,186 @@ fn main() {
         .build()
         .unwrap()
         .block_on(async move {
-            let mut tasks = vec![];
-
             let max_conn = cli.max_conn;
             let timeout = cli.timeout as i32;
 
-            let mut files = vec![];
-            let (tx, mut rx) = mpsc::channel(50);
-
-            let mut outfile = None;
-            let mut limit = 0;
-            let mut format = "default".to_string();
-
-            let mut is_server = false;
-            let mut host = "127.0.0.1".to_string();
-            let mut port = 8080;
-
             if !cli.skip_version_check {
-                tasks.push(task::spawn(check_version()));
+                task::spawn(check_version());
             }
-
-            match cli.sub {
-                Commands::Grab(grab_args) => {
-                    outfile = grab_args.outfile.clone();
-                    limit = grab_args.limit;
-                    format = grab_args.format.clone();
-
-                    let tx = tx.clone();
-                    tasks.push(task::spawn(handle_grab_command(grab_args, tx)))
-                }
-                Commands::Find(find_args) => {
-                    outfile = find_args.outfile.clone();
-                    limit = find_args.limit;
-                    format = find_args.format.clone();
-
-                    let mut checker = Checker::new().await;
-                    checker.max_tries = find_args.max_tries as i32;
-                    checker.timeout = timeout;
-                    checker.support_cookie = find_args.support_cookies;
-                    checker.support_referer = find_args.support_referer;
-                    checker.expected_types = find_args.types.clone();
-                    checker.expected_levels = find_args.levels;
-                    checker.expected_countries = find_args.countries;
-
-                    let ext_ip = checker.ext_ip.clone();
-
-                    let expected_types = find_args.types.clone();
-                    let verify_ssl = false;
-                    tasks.push(task::spawn(async move {
-                        checker::check_judges(verify_ssl, ext_ip, expected_types).await;
-                    }));
-
-                    files.extend(find_args.files.clone());
-
-                    let tx = tx.clone();
-                    tasks.push(task::spawn(handle_find_command(checker, max_conn, tx)));
-                }
-
-                Commands::Serve(serve_args) => {
-                    is_server = true;
-
-                    host = serve_args.host;
-                    port = serve_args.port;
-
-                    let mut checker = Checker::new().await;
-                    checker.max_tries = serve_args.max_tries as i32;
-                    checker.support_cookie = true;
-                    checker.support_referer = true;
-
-                    checker.expected_types = serve_args.types.clone();
-                    checker.expected_levels = serve_args.levels;
-                    checker.expected_countries = serve_args.countries;
-
-                    let ext_ip = checker.ext_ip.clone();
-
-                    let expected_types = serve_args.types.clone();
-                    let verify_ssl = false;
-                    tasks.push(task::spawn(async move {
-                        checker::check_judges(verify_ssl, ext_ip, expected_types).await;
-                    }));
-                    files.extend(serve_args.files.clone());
-
-                    let tx = tx.clone();
-                    tasks.push(task::spawn(handle_find_command(checker, max_conn, tx)));
-                }
+            if let Some(addr) = cli.metrics {
+                task::spawn(metrics::serve(addr));
             }
-
-            if !files.is_empty() {
-                tasks.push(task::spawn(async move {
-                    handle_file_input(files).await;
-                    let mut stop_file_loop = STOP_FIND_LOOP.lock();
-                    *stop_file_loop = true
-                }))
-            } else {
-                if !is_server {
-                    log::info!("Start collecting proxies.. ");
-                }
-
-                /* providers */
-                tasks.push(tokio::task::spawn(async {
-                    let dur = Duration::from_secs(60);
-                    loop {
-                        providers::run_all_providers(3).await;
-                        log::debug!("Next cycle starts at {:?}", dur);
-                        time::sleep(dur).await;
+            let store = match &cli.db {
+                Some(path) => match ProxyStore::open(path) {
+                    Ok(store) => Some(Arc::new(store.store_credentials(cli.db_credentials))),
+                    Err(err) => {
+                        eprintln!("Failed to open {}: {}", path.display(), err);
+                        std::process::exit(1);
                     }
-                }));
-            }
-
-            if is_server {
-                tasks.push(tokio::task::spawn(async move {
-                    let server = Server::new(host.as_str(), port);
-                    server.start().await;
-                }));
-
-                loop {
-                    if let Some(Some(proxy)) = rx.recv().await {
-                        while LIVE_PROXIES.is_full() {
-                            continue;
+                },
+                None => None,
+            };
+
+            let (proxies, outfile, format): (Pin<Box<dyn Stream<Item = Proxy>>>, _, _) =
+                match cli.sub {
+                    Commands::Grab(grab_args) => {
+                        log::info!("Start collecting proxies.. ");
+                        let candidates = Arc::new(Candidates::new());
+                        let providers_candidates = Arc::clone(&candidates);
+                        task::spawn(async move {
+                            let dur = Duration::from_secs(60);
+                            loop {
+                                providers::run_all_providers(3, &providers_candidates).await;
+                                log::debug!("Next cycle starts at {:?}", dur);
+                                time::sleep(dur).await;
+                            }
+                        });
+
+                        let (tx, rx) = mpsc::channel(50);
+                        let outfile = grab_args.outfile.clone();
+                        let format = grab_args.format.clone();
+                        let limit = grab_args.limit;
+                        task::spawn(handle_grab_command(grab_args, candidates, tx));
+
+                        let proxies = stream::unfold(rx, |mut rx| async {
+                            rx.recv().await.map(|proxy| (proxy, rx))
+                        });
+                        let proxies: Pin<Box<dyn Stream<Item = Proxy>>> = if limit == 0 {
+                            Box::pin(proxies)
+                        } else {
+                            Box::pin(proxies.take(limit))
+                        };
+                        (proxies, outfile, format)
+                    }
+                    Commands::Recheck(recheck_args) => {
+                        handle_recheck_command(recheck_args, max_conn, timeout, store).await;
+                        return;
+                    }
+                    Commands::Find(find_args) => {
+                        if find_args.files.is_empty() {
+                            log::info!("Start collecting proxies.. ");
+                        }
+                        let mut finder = Finder::builder()
+                            .types(find_args.types)
+                            .countries(find_args.countries)
+                            .levels(find_args.levels)
+                            .files(find_args.files)
+                            .limit(find_args.limit)
+                            .max_conn(max_conn)
+                            .max_tries(find_args.max_tries as i32)
+                            .timeout(timeout)
+                            .support_cookies(find_args.support_cookies)
+                            .support_referer(find_args.support_referer);
+                        if let Some(store) = store {
+                            finder = finder.store(store);
                         }
-                        LIVE_PROXIES.push(proxy).unwrap();
+                        let proxies = match finder.build().stream().await {
+                            Ok(proxies) => proxies,
+                            Err(err) => exit_on_error(err).await,
+                        };
+                        (Box::pin(proxies), find_args.outfile, find_args.format)
                     }
-                }
-            } else {
-                let mut output: Pin<Box<dyn AsyncWrite>> = if let Some(path) = outfile {
-                    let file = File::create(path).await.unwrap();
-                    Box::pin(file)
-                } else {
-                    Box::pin(stdout())
-                };
-
-                let mut open_list = false;
-                let mut counter = limit;
-
-                while let Some(proxy) = rx.recv().await {
-                    let stop = proxy.is_none() || (limit != 0 && counter <= 1);
-                    if let Some(proxy) = proxy {
-                        if format == "json" && !open_list {
-                            output.write_all(b"[").await.unwrap();
-                            open_list = true;
+                    Commands::Serve(serve_args) => {
+                        let mut users = serve_args.auth;
+                        if let Some(path) = &serve_args.auth_file {
+                            match load_users(path) {
+                                Ok(loaded) => users.extend(loaded),
+                                Err(err) => {
+                                    eprintln!("Failed to read {}: {}", path.display(), err);
+                                    std::process::exit(1);
+                                }
+                            }
                         }
 
-                        let msg = match format.as_str() {
-                            "text" => proxy.as_text(),
-                            "json" => proxy.as_json(),
-                            _ => format!("{}", proxy),
+                        let mut finder = Finder::builder()
+                            .types(serve_args.types)
+                            .countries(serve_args.countries)
+                            .levels(serve_args.levels)
+                            .files(serve_args.files)
+                            .max_conn(max_conn)
+                            .max_tries(serve_args.max_tries as i32)
+                            .timeout(timeout)
+                            .support_cookies(true)
+                            .support_referer(true);
+                        if let Some(store) = store {
+                            finder = finder.store(store);
+                        }
+                        let mut proxies = match finder.build().stream().await {
+                            Ok(proxies) => proxies,
+                            Err(err) => exit_on_error(err).await,
                         };
 
-                        output.write_all(msg.as_bytes()).await.unwrap();
-                        if stop {
-                            output
-                                .write_all(if format == "json" { b"]" } else { b"" })
-                                .await
-                                .unwrap();
-                        } else if format == "json" {
-                            output.write_all(b",").await.unwrap();
+                        let host = serve_args.host;
+                        let port = serve_args.port;
+                        let socks_port = serve_args.socks_port;
+                        let admin_port = serve_args.admin_port;
+                        let admin_host = serve_args.admin_host;
+                        let max_attempts = serve_args.max_attempts;
+                        let strategy = serve_args.strategy;
+                        let sticky = serve_args.sticky;
+                        let session_ttl = serve_args.session_ttl;
+                        let allow = serve_args.allow;
+                        let health_interval = serve_args.health_interval;
+                        let health_concurrency = serve_args.health_concurrency;
+                        let deny = serve_args.deny;
+                        task::spawn(async move {
+                            let mut server = Server::new(host.as_str(), port);
+                            server.socks_port = socks_port;
+                            server.admin_port = admin_port;
+                            server.admin_host = admin_host;
+                            server.max_attempts = max_attempts;
+                            server.strategy = strategy;
+                            server.session_keys = sticky;
+                            server.session_ttl = session_ttl;
+                            server.users = users;
+                            server.allow = allow;
+                            server.deny = deny;
+                            server.health_interval = health_interval;
+                            server.health_concurrency = health_concurrency;
+                            server.start().await;
+                        });
+
+                        while let Some(proxy) = proxies.next().await {
+                            let _ = LIVE_PROXIES.push_wait(proxy).await;
                         }
-                        output.write_all(b"\n").await.unwrap();
-                    }
-                    if limit != 0 {
-                        counter -= 1;
+                        return;
                     }
+                };
 
-                    if stop {
-                        std::process::exit(0);
-                    }
+            let mut output: Pin<Box<dyn AsyncWrite>> = if let Some(path) = outfile {
+                let file = File::create(path).await.unwrap();
+                Box::pin(file)
+            } else {
+                Box::pin(stdout())
+            };
+
+            let mut proxies = proxies;
+            let mut open_list = false;
+            while let Some(proxy) = proxies.next().await {
+                if format == "json" {
+                    output
+                        .write_all(if open_list { b",\n" } else { b"[" })
+                        .await
+                        .unwrap();
+                    open_list = true;
                 }
+
+                let msg = match format.as_str() {
+                    "text" => proxy.as_text(),
+                    "json" => proxy.as_json(),
+                    _ => format!("{}", proxy),
+                };
+
+                output.write_all(msg.as_bytes()).await.unwrap();
+                if format != "json" {
+                    output.write_all(b"\n").await.unwrap();
+                }
+                output.flush().await.unwrap();
+            }
+            if open_list {
+                output.write_all(b"]\n").await.unwrap();
             }
+            output.flush().await.unwrap();
+            std::process::exit(0);
         });
 }
@@ -0,0 +1,130 @@
+//! Prometheus metrics of the providers, the checker and the server.
+
+use hyper::{
+    header::CONTENT_TYPE, server::conn::Http, service::service_fn, Body, Method, Request, Response,
+    StatusCode,
+};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use tokio::net::TcpListener;
+
+use crate::server::proxy_pool::LIVE_PROXIES;
+
+lazy_static! {
+    pub static ref PROVIDER_FETCHES: IntCounterVec = register_int_counter_vec!(
+        "proxy_rs_provider_fetches_total",
+        "Times a provider was fetched",
+        &["provider"]
+    )
+    .unwrap();
+    pub static ref PROVIDER_PROXIES: IntCounterVec = register_int_counter_vec!(
+        "proxy_rs_provider_proxies_total",
+        "Proxies scraped from a provider, `new` are the ones not seen before",
+        &["provider", "state"]
+    )
+    .unwrap();
+    pub static ref CHECKS: IntCounterVec = register_int_counter_vec!(
+        "proxy_rs_checks_total",
+        "Checks of a proxy against a protocol",
+        &["protocol", "result"]
+    )
+    .unwrap();
+    pub static ref CHECK_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "proxy_rs_check_errors_total",
+        "Errors met while checking proxies",
+        &["protocol", "reason"]
+    )
+    .unwrap();
+    pub static ref JUDGES: IntGaugeVec =
+        register_int_gauge_vec!("proxy_rs_judges", "Working judges", &["scheme"]).unwrap();
+    pub static ref SERVER_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "proxy_rs_server_requests_total",
+        "Requests served by the local proxy server",
+        &["protocol", "status"]
+    )
+    .unwrap();
+    pub static ref SERVER_BYTES: IntCounterVec = register_int_counter_vec!(
+        "proxy_rs_server_tunnel_bytes_total",
+        "Bytes relayed through CONNECT and SOCKS5 tunnels",
+        &["direction"]
+    )
+    .unwrap();
+    pub static ref UPSTREAM_LATENCY: HistogramVec = register_histogram_vec!(
+        "proxy_rs_server_upstream_connect_seconds",
+        "Time to connect to an upstream proxy",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref POOL_SIZE: IntGaugeVec = register_int_gauge_vec!(
+        "proxy_rs_server_pool_size",
+        "Proxies known to the local proxy server",
+        &["state"]
+    )
+    .unwrap();
+}
+
+/// Counts bytes relayed by a tunnel, as returned by `copy_bidirectional`.
+pub fn record_tunnel(client_to_upstream: u64, upstream_to_client: u64) {
+    SERVER_BYTES
+        .with_label_values(&["sent"])
+        .inc_by(client_to_upstream);
+    SERVER_BYTES
+        .with_label_values(&["received"])
+        .inc_by(upstream_to_client);
+}
+
+/// All metrics in the Prometheus text format.
+pub fn encode() -> String {
+    POOL_SIZE
+        .with_label_values(&["queue"])
+        .set(LIVE_PROXIES.len() as i64);
+
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+pub fn response() -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, TextEncoder::new().format_type())
+        .body(Body::from(encode()))
+        .unwrap()
+}
+
+/// Serves `GET /metrics` on `addr` forever.
+pub async fn serve(addr: String) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("Failed to listen on {}: {}", addr, err);
+            return;
+        }
+    };
+    log::info!("Metrics available at http://{}/metrics", addr);
+    loop {
+        if let Ok((stream, _)) = listener.accept().await {
+            tokio::task::spawn(async move {
+                let service = service_fn(|request: Request<Body>| async move {
+                    Ok::<_, hyper::Error>(
+                        if request.method() == Method::GET && request.uri().path() == "/metrics" {
+                            response()
+                        } else {
+                            Response::builder()
+                                .status(StatusCode::NOT_FOUND)
+                                .body(Body::empty())
+                                .unwrap()
+                        },
+                    )
+                });
+                if let Err(err) = Http::new().serve_connection(stream, service).await {
+                    log::error!("Metrics connection error: {}", err);
+                }
+            });
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{judge::Judge, proxy::Proxy, utils::http::response::ResponseParser};
+use async_trait::async_trait;
+
+use crate::{error::ProxyError, judge::Judge, proxy::Proxy, utils::http::response::ResponseParser};
+
+use super::Negotiator;
 
 #[derive(Debug, Clone)]
 pub struct Connect25Negotiator {
@@ -7,11 +11,33 @@ pub struct Connect25Negotiator {
     pub use_full_path: bool,
 }
 
-impl Connect25Negotiator {
-    pub async fn negotiate(&self, proxy: &mut Proxy, judge: &Judge) -> bool {
+#[async_trait]
+impl Negotiator for Connect25Negotiator {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check_anon_lvl(&self) -> bool {
+        self.check_anon_lvl
+    }
+
+    fn use_full_path(&self) -> bool {
+        self.use_full_path
+    }
+
+    fn judge_scheme(&self) -> &str {
+        "SMTP"
+    }
+
+    fn need_request(&self) -> bool {
+        false
+    }
+
+    async fn negotiate(&self, proxy: &mut Proxy, judge: &Judge) -> bool {
         let connect_payload = format!(
-            "CONNECT {0}:25 HTTP/1.1\r\nHost: {0}\r\nConnection: keep-alive\r\n\r\n",
-            judge.host
+            "CONNECT {0}:25 HTTP/1.1\r\nHost: {0}\r\nConnection: keep-alive\r\n{1}\r\n",
+            judge.host,
+            proxy.auth_header()
         );
         proxy.send(connect_payload.as_bytes()).await;
 
@@ -21,11 +47,11 @@ impl Connect25Negotiator {
                 if status_code == 200 {
                     return true;
                 }
-                proxy.log(
-                    format!("Connect: failed. HTTP status: {}", status_code).as_str(),
-                    None,
-                    Some("bad_status_error".to_string()),
-                );
+                let error = match status_code {
+                    407 => ProxyError::AuthRequired,
+                    status_code => ProxyError::BadStatus(status_code),
+                };
+                proxy.log_error(error, None);
             }
         }
         false
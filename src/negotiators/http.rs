@@ -1,3 +1,9 @@
+use async_trait::async_trait;
+
+use crate::{judge::Judge, proxy::Proxy};
+
+use super::Negotiator;
+
 #[derive(Debug, Clone)]
 pub struct HttpNegotiator {
     pub name: String,
@@ -5,8 +11,21 @@ pub struct HttpNegotiator {
     pub use_full_path: bool,
 }
 
-impl HttpNegotiator {
-    pub async fn negotiate(&self) -> bool {
+#[async_trait]
+impl Negotiator for HttpNegotiator {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check_anon_lvl(&self) -> bool {
+        self.check_anon_lvl
+    }
+
+    fn use_full_path(&self) -> bool {
+        self.use_full_path
+    }
+
+    async fn negotiate(&self, _proxy: &mut Proxy, _judge: &Judge) -> bool {
         true
     }
 }
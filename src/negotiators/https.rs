@@ -1,5 +1,9 @@
+use async_trait::async_trait;
+
 use crate::{judge::Judge, proxy::Proxy};
 
+use super::Negotiator;
+
 #[derive(Debug, Clone)]
 pub struct HttpsNegotiator {
     pub name: String,
@@ -7,11 +11,33 @@ pub struct HttpsNegotiator {
     pub use_full_path: bool,
 }
 
-impl HttpsNegotiator {
-    pub async fn negotiate(&self, proxy: &mut Proxy, judge: &Judge) -> bool {
+#[async_trait]
+impl Negotiator for HttpsNegotiator {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check_anon_lvl(&self) -> bool {
+        self.check_anon_lvl
+    }
+
+    fn use_full_path(&self) -> bool {
+        self.use_full_path
+    }
+
+    fn judge_scheme(&self) -> &str {
+        "HTTPS"
+    }
+
+    fn need_connection(&self) -> bool {
+        false
+    }
+
+    async fn negotiate(&self, proxy: &mut Proxy, judge: &Judge) -> bool {
         let connect_payload = format!(
-            "CONNECT {0}:443 HTTP/1.1\r\nHost: {0}\r\nConnection: keep-alive\r\n\r\n",
-            judge.host
+            "CONNECT {0}:443 HTTP/1.1\r\nHost: {0}\r\nConnection: keep-alive\r\n{1}\r\n",
+            judge.host,
+            proxy.auth_header()
         );
         proxy.connect_ssl(connect_payload.as_bytes()).await
     }
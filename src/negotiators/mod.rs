@@ -4,3 +4,205 @@ pub mod http;
 pub mod https;
 pub mod socks4;
 pub mod socks5;
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use tokio::net::TcpStream;
+
+use crate::{
+    error::ProxyError,
+    judge::Judge,
+    proxy::{Credentials, Proxy},
+    types::ProxyProtocol,
+    utils::host_port,
+};
+
+use self::{
+    connect_25::Connect25Negotiator, connect_80::Connect80Negotiator, http::HttpNegotiator,
+    https::HttpsNegotiator, socks4::Socks4Negotiator, socks5::Socks5Negotiator,
+};
+
+lazy_static! {
+    pub static ref NEGOTIATORS: RwLock<NegotiatorRegistry> =
+        RwLock::new(NegotiatorRegistry::default());
+}
+
+/// Destination a proxy is asked to connect to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetAddr {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl TargetAddr {
+    pub fn new(host: &str, port: u16) -> Self {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        match host.parse::<IpAddr>() {
+            Ok(ip) => TargetAddr::Ip(SocketAddr::new(ip, port)),
+            Err(_) => TargetAddr::Domain(host.to_string(), port),
+        }
+    }
+
+    /// Resolved address of the judge, or its hostname when it was not resolved.
+    pub fn from_judge(judge: &Judge) -> Self {
+        let port = judge.url.port_or_known_default().unwrap_or(80);
+        match judge
+            .ip_address
+            .as_ref()
+            .and_then(|ip| ip.parse::<IpAddr>().ok())
+        {
+            Some(ip) => TargetAddr::Ip(SocketAddr::new(ip, port)),
+            None => TargetAddr::Domain(judge.host.clone(), port),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            TargetAddr::Ip(addr) => addr.port(),
+            TargetAddr::Domain(_, port) => *port,
+        }
+    }
+}
+
+impl std::fmt::Display for TargetAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetAddr::Ip(addr) => write!(f, "{}", addr),
+            TargetAddr::Domain(host, port) => f.write_str(&host_port(host, *port)),
+        }
+    }
+}
+
+/// Logs the outcome of a client handshake run over the proxy connection.
+fn log_handshake(proxy: &mut Proxy, result: Result<(), ProxyError>, stime: Instant) -> bool {
+    match result {
+        Ok(_) => {
+            proxy.log("Request is granted", Some(stime.elapsed()));
+            true
+        }
+        Err(e) => {
+            proxy.log_error(e, Some(stime.elapsed()));
+            false
+        }
+    }
+}
+
+#[async_trait]
+pub trait Negotiator: Send + Sync {
+    fn name(&self) -> &str;
+    fn check_anon_lvl(&self) -> bool;
+    fn use_full_path(&self) -> bool;
+
+    /// Scheme of the judges used to verify this protocol.
+    fn judge_scheme(&self) -> &str {
+        "HTTP"
+    }
+
+    /// Whether the checker opens the tcp connection before negotiating.
+    fn need_connection(&self) -> bool {
+        true
+    }
+
+    /// Whether a request is sent to the judge once negotiation succeeds.
+    fn need_request(&self) -> bool {
+        true
+    }
+
+    async fn negotiate(&self, proxy: &mut Proxy, judge: &Judge) -> bool;
+
+    /// Schemes `serve` can relay clients through with [`Negotiator::tunnel`].
+    /// Only read for custom protocols, built-in ones are served natively.
+    fn schemes(&self) -> Vec<ProxyProtocol> {
+        vec![]
+    }
+
+    /// Asks the proxy at the other end of `stream` to connect to `target`.
+    async fn tunnel(
+        &self,
+        _stream: &mut TcpStream,
+        _target: &TargetAddr,
+        _credentials: Option<&Credentials>,
+    ) -> Result<(), ProxyError> {
+        Err(ProxyError::InvalidInput(
+            "protocol does not support tunneling",
+        ))
+    }
+}
+
+#[derive(Clone)]
+pub struct NegotiatorRegistry {
+    order: Vec<ProxyProtocol>,
+    negotiators: HashMap<ProxyProtocol, Arc<dyn Negotiator>>,
+}
+
+impl NegotiatorRegistry {
+    pub fn new() -> Self {
+        Self {
+            order: vec![],
+            negotiators: HashMap::new(),
+        }
+    }
+
+    /// Registers a negotiator, replacing the previous one for the same protocol.
+    pub fn register<N: Negotiator + 'static>(&mut self, proto: ProxyProtocol, negotiator: N) {
+        if !self.order.contains(&proto) {
+            self.order.push(proto);
+        }
+        self.negotiators.insert(proto, Arc::new(negotiator));
+    }
+
+    pub fn get(&self, proto: &ProxyProtocol) -> Option<Arc<dyn Negotiator>> {
+        self.negotiators.get(proto).cloned()
+    }
+
+    /// Registered protocols, in registration order.
+    pub fn protocols(&self) -> Vec<ProxyProtocol> {
+        self.order.clone()
+    }
+}
+
+impl Default for NegotiatorRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        for proto in ProxyProtocol::BUILTIN {
+            match proto {
+                ProxyProtocol::Connect80 => {
+                    registry.register(proto, Connect80Negotiator::default())
+                }
+                ProxyProtocol::Connect25 => {
+                    registry.register(proto, Connect25Negotiator::default())
+                }
+                ProxyProtocol::Socks5 => registry.register(proto, Socks5Negotiator::default()),
+                ProxyProtocol::Socks4 => registry.register(proto, Socks4Negotiator::default()),
+                ProxyProtocol::Socks4a => registry.register(proto, Socks4Negotiator::socks4a()),
+                ProxyProtocol::Https => registry.register(proto, HttpsNegotiator::default()),
+                ProxyProtocol::Http => registry.register(proto, HttpNegotiator::default()),
+                ProxyProtocol::Custom(_) => {}
+            }
+        }
+        registry
+    }
+}
+
+impl std::fmt::Debug for NegotiatorRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.order.iter()).finish()
+    }
+}
+
+/// Makes `negotiator` available to every checker under `proto`.
+pub fn register_negotiator<N: Negotiator + 'static>(proto: ProxyProtocol, negotiator: N) {
+    NEGOTIATORS.write().register(proto, negotiator)
+}
+
+pub fn get_negotiator(proto: &ProxyProtocol) -> Option<Arc<dyn Negotiator>> {
+    NEGOTIATORS.read().get(proto)
+}
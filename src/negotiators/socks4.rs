@@ -1,61 +1,68 @@
-use std::{io::Cursor, net::Ipv4Addr};
+use std::{
+    io::Cursor,
+    net::{Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
 
+use async_trait::async_trait;
 use byteorder::BigEndian;
 use byteorder_pack::PackTo;
-use tokio::io::AsyncReadExt;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::timeout,
+};
 
-use crate::proxy::Proxy;
+use crate::{error::ProxyError, judge::Judge, proxy::Proxy};
+
+use super::{log_handshake, Negotiator, TargetAddr};
 
 #[derive(Debug, Clone)]
 pub struct Socks4Negotiator {
     pub name: String,
     pub check_anon_lvl: bool,
     pub use_full_path: bool,
+    /// Send the judge hostname instead of its ip (SOCKS4a).
+    pub remote_dns: bool,
 }
 
 impl Socks4Negotiator {
-    pub async fn negotiate(&self, proxy: &mut Proxy) -> bool {
-        let bip = proxy.host.parse::<Ipv4Addr>();
-        if bip.is_err() {
-            return false;
-        }
-
-        let data = (4u8, 1u8, proxy.port, bip.unwrap().octets(), 0u8);
-        let mut buf = Cursor::new(Vec::new());
-        if data.pack_to::<BigEndian, _>(&mut buf).is_err() {
-            return false;
+    pub fn socks4a() -> Self {
+        Self {
+            name: "SOCKS4a".to_string(),
+            remote_dns: true,
+            ..Default::default()
         }
-        let packet = buf.into_inner();
-
-        proxy.send(packet.as_slice()).await;
+    }
+}
 
-        if let Some(data) = proxy.recv(8).await {
-            let mut data = data.as_slice();
+#[async_trait]
+impl Negotiator for Socks4Negotiator {
+    fn name(&self) -> &str {
+        &self.name
+    }
 
-            let version = data.read_u8().await;
-            if version.is_err() || version.unwrap() != 0 {
-                proxy.log(
-                    "Invalid response version",
-                    None,
-                    Some("invalid_response_version".to_string()),
-                );
-                return false;
-            }
+    fn check_anon_lvl(&self) -> bool {
+        self.check_anon_lvl
+    }
 
-            let resp = data.read_u8().await;
-            if resp.is_err() || resp.unwrap() != 90 {
-                proxy.log(
-                    "Request rejected or Failed",
-                    None,
-                    Some("request_failed".to_string()),
-                );
-                return false;
-            }
+    fn use_full_path(&self) -> bool {
+        self.use_full_path
+    }
 
-            proxy.log("Request is granted", None, None);
-            return true;
-        }
-        false
+    async fn negotiate(&self, proxy: &mut Proxy, judge: &Judge) -> bool {
+        let target = if self.remote_dns {
+            TargetAddr::Domain(judge.host.clone(), TargetAddr::from_judge(judge).port())
+        } else {
+            TargetAddr::from_judge(judge)
+        };
+        let stime = Instant::now();
+        let result = timeout(
+            Duration::from_secs(proxy.timeout as u64),
+            handshake(proxy, &target, self.remote_dns),
+        )
+        .await
+        .unwrap_or(Err(ProxyError::HandshakeTimeout));
+        log_handshake(proxy, result, stime)
     }
 }
 
@@ -65,6 +72,43 @@ impl Default for Socks4Negotiator {
             name: "SOCKS4".to_string(),
             check_anon_lvl: false,
             use_full_path: false,
+            remote_dns: false,
         }
     }
 }
+
+/// Client side of a SOCKS4 handshake asking the proxy to connect to `target`.
+///
+/// With `remote_dns` (SOCKS4a) a domain target is resolved by the proxy,
+/// otherwise `target` must be an ipv4 address.
+pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    target: &TargetAddr,
+    remote_dns: bool,
+) -> Result<(), ProxyError> {
+    let dst_ip = match target {
+        TargetAddr::Ip(SocketAddr::V4(addr)) => *addr.ip(),
+        TargetAddr::Domain(_, _) if remote_dns => Ipv4Addr::new(0, 0, 0, 1),
+        _ => return Err(ProxyError::InvalidInput("target has no ipv4 address")),
+    };
+
+    let data = (4u8, 1u8, target.port(), dst_ip.octets(), 0u8);
+    let mut buf = Cursor::new(Vec::new());
+    data.pack_to::<BigEndian, _>(&mut buf)?;
+    let mut packet = buf.into_inner();
+    if let TargetAddr::Domain(host, _) = target {
+        packet.extend(host.as_bytes());
+        packet.push(0u8);
+    }
+    stream.write_all(packet.as_slice()).await?;
+
+    let mut reply = [0u8; 8];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 0 {
+        return Err(ProxyError::InvalidSocksReply("invalid response version"));
+    }
+    if reply[1] != 90 {
+        return Err(ProxyError::RequestRejected(reply[1]));
+    }
+    Ok(())
+}
@@ -1,9 +1,22 @@
-use std::{io::Cursor, net::Ipv4Addr};
+use std::{
+    io::{self, Error, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
 
-use byteorder::BigEndian;
-use byteorder_pack::PackTo;
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::timeout,
+};
 
-use crate::proxy::Proxy;
+use crate::{
+    error::ProxyError,
+    judge::Judge,
+    proxy::{Credentials, Proxy},
+};
+
+use super::{log_handshake, Negotiator, TargetAddr};
 
 #[derive(Debug, Clone)]
 pub struct Socks5Negotiator {
@@ -12,59 +25,31 @@ pub struct Socks5Negotiator {
     pub use_full_path: bool,
 }
 
-impl Socks5Negotiator {
-    pub async fn negotiate(&self, proxy: &mut Proxy) -> bool {
-        let packet = [5, 1, 0];
-        proxy.send(&packet).await;
-        if let Some(data) = proxy.recv(2).await {
-            if data[0] != 0x05 {
-                proxy.log("Invalid version", None, Some("invalid_version".to_string()));
-                return false;
-            }
-            if data[1] == 0xff {
-                proxy.log(
-                    "Failed (auth is required)",
-                    None,
-                    Some("auth_is_required".to_string()),
-                );
-                return false;
-            }
-            if data[1] != 0x00 {
-                proxy.log(
-                    "Failed (invalid data)",
-                    None,
-                    Some("invalid_data".to_string()),
-                );
-                return false;
-            }
+#[async_trait]
+impl Negotiator for Socks5Negotiator {
+    fn name(&self) -> &str {
+        &self.name
+    }
 
-            let bip = proxy.host.parse::<Ipv4Addr>();
-            if bip.is_err() {
-                return false;
-            }
+    fn check_anon_lvl(&self) -> bool {
+        self.check_anon_lvl
+    }
 
-            let data = (5u8, 1u8, 0u8, 1u8, bip.unwrap().octets(), proxy.port);
-            let mut buf = Cursor::new(Vec::new());
-            if data.pack_to::<BigEndian, _>(&mut buf).is_err() {
-                return false;
-            }
-            let packet = buf.into_inner();
-
-            proxy.send(packet.as_slice()).await;
-            if let Some(data) = proxy.recv(10).await {
-                if data[0] != 0x05 || data[1] != 0x00 {
-                    proxy.log(
-                        "Failed (invalid data)",
-                        None,
-                        Some("invalid_data".to_string()),
-                    );
-                    return false;
-                }
-                proxy.log("Request is granted", None, None);
-                return true;
-            }
-        }
-        false
+    fn use_full_path(&self) -> bool {
+        self.use_full_path
+    }
+
+    async fn negotiate(&self, proxy: &mut Proxy, judge: &Judge) -> bool {
+        let target = TargetAddr::from_judge(judge);
+        let credentials = proxy.credentials.clone();
+        let stime = Instant::now();
+        let result = timeout(
+            Duration::from_secs(proxy.timeout as u64),
+            handshake(proxy, &target, credentials.as_ref()),
+        )
+        .await
+        .unwrap_or(Err(ProxyError::HandshakeTimeout));
+        log_handshake(proxy, result, stime)
     }
 }
 
@@ -77,3 +62,114 @@ impl Default for Socks5Negotiator {
         }
     }
 }
+
+/// Encodes `target` as `ATYP | DST.ADDR | DST.PORT`.
+pub fn encode_target(target: &TargetAddr) -> Option<Vec<u8>> {
+    let mut packet = vec![];
+    match target {
+        TargetAddr::Ip(SocketAddr::V4(addr)) => {
+            packet.push(0x01);
+            packet.extend(addr.ip().octets());
+        }
+        TargetAddr::Ip(SocketAddr::V6(addr)) => {
+            packet.push(0x04);
+            packet.extend(addr.ip().octets());
+        }
+        TargetAddr::Domain(host, _) => {
+            if host.len() > 255 {
+                return None;
+            }
+            packet.push(0x03);
+            packet.push(host.len() as u8);
+            packet.extend(host.as_bytes());
+        }
+    }
+    packet.extend(target.port().to_be_bytes());
+    Some(packet)
+}
+
+/// Reads the address and port that follow an `ATYP` byte.
+pub async fn read_target<R: AsyncRead + Unpin>(reader: &mut R, atyp: u8) -> io::Result<TargetAddr> {
+    let target = match atyp {
+        0x01 => {
+            let mut addr = [0u8; 4];
+            reader.read_exact(&mut addr).await?;
+            TargetAddr::Ip(SocketAddr::new(Ipv4Addr::from(addr).into(), 0))
+        }
+        0x04 => {
+            let mut addr = [0u8; 16];
+            reader.read_exact(&mut addr).await?;
+            TargetAddr::Ip(SocketAddr::new(Ipv6Addr::from(addr).into(), 0))
+        }
+        0x03 => {
+            let len = reader.read_u8().await?;
+            let mut host = vec![0u8; len as usize];
+            reader.read_exact(&mut host).await?;
+            let host = String::from_utf8(host)
+                .map_err(|_| Error::new(ErrorKind::InvalidData, "invalid domain name"))?;
+            TargetAddr::Domain(host, 0)
+        }
+        _ => return Err(Error::new(ErrorKind::InvalidData, "invalid address type")),
+    };
+    let port = reader.read_u16().await?;
+    Ok(match target {
+        TargetAddr::Ip(addr) => TargetAddr::Ip(SocketAddr::new(addr.ip(), port)),
+        TargetAddr::Domain(host, _) => TargetAddr::Domain(host, port),
+    })
+}
+
+/// Client side of a SOCKS5 handshake asking the proxy to connect to `target`.
+pub async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    target: &TargetAddr,
+    credentials: Option<&Credentials>,
+) -> Result<(), ProxyError> {
+    let methods: &[u8] = if credentials.is_some() {
+        &[5, 2, 0x00, 0x02]
+    } else {
+        &[5, 1, 0x00]
+    };
+    stream.write_all(methods).await?;
+
+    let mut data = [0u8; 2];
+    stream.read_exact(&mut data).await?;
+    if data[0] != 0x05 {
+        return Err(ProxyError::InvalidSocksReply("invalid version"));
+    }
+    match (data[1], credentials) {
+        (0x00, _) => {}
+        (0x02, Some(credentials)) => {
+            let username = credentials.username.as_bytes();
+            let password = credentials.password.as_bytes();
+            if username.len() > 255 || password.len() > 255 {
+                return Err(ProxyError::InvalidInput("credentials too long"));
+            }
+            let mut packet = vec![0x01, username.len() as u8];
+            packet.extend(username);
+            packet.push(password.len() as u8);
+            packet.extend(password);
+            stream.write_all(packet.as_slice()).await?;
+
+            stream.read_exact(&mut data).await?;
+            if data[0] != 0x01 || data[1] != 0x00 {
+                return Err(ProxyError::AuthFailed);
+            }
+        }
+        _ => return Err(ProxyError::AuthRequired),
+    }
+
+    let mut packet = vec![5u8, 1u8, 0u8];
+    packet.extend(encode_target(target).ok_or(ProxyError::InvalidInput("domain name too long"))?);
+    stream.write_all(packet.as_slice()).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 0x05 {
+        return Err(ProxyError::InvalidSocksReply("invalid version"));
+    }
+    if reply[1] != 0x00 {
+        return Err(ProxyError::RequestRejected(reply[1]));
+    }
+    read_target(stream, reply[3]).await?;
+    Ok(())
+}
@@ -6,42 +6,50 @@ use hyper_tls::HttpsConnector;
 use regex::Regex;
 use tokio::time::timeout;
 
-use crate::utils::{
-    http::{hyper_client, random_useragent},
-    vec_of_strings,
+use crate::{
+    types::ProxyProtocol,
+    utils::http::{hyper_client, random_useragent},
 };
 
+/// A web page listing proxies.
 #[derive(Debug, Clone)]
 pub struct Provider {
     pub url: &'static str,
+    /// Finds more pages to scrape in a page, given its html and `scheme://host`.
     pub new_urls: Option<fn(&String, String) -> Vec<String>>,
+    /// The maximum number of times `new_urls` is followed.
     pub max_depth: u32,
+    /// Regex with `ip` and `port` groups matching a proxy.
     pub pattern: &'static str,
-    pub proto: Vec<String>,
+    /// Protocols the listed proxies are expected to support.
+    pub proto: Vec<ProxyProtocol>,
     pub name: &'static str,
+    /// Seconds a page may take to load.
     pub timeout: i32,
 }
 
 impl Default for Provider {
     fn default() -> Self {
         Self {
-            pattern: r#"(?P<ip>(?:\d+\.?){4})\:(?P<port>\d+)"#,
+            pattern: r#"(?P<ip>(?:\d+\.?){4}|\[[0-9a-fA-F:.]+\])\:(?P<port>\d+)"#,
             url: "",
             name: "",
             new_urls: None,
             max_depth: 1,
             timeout: 5,
-            proto: vec_of_strings![
-                "HTTP",
-                "HTTPS",
-                "SOCKS4",
-                "SOCKS5",
-                "CONNECT:80",
-                "CONNECT:25"
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Https,
+                ProxyProtocol::Socks4,
+                ProxyProtocol::Socks4a,
+                ProxyProtocol::Socks5,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Connect25,
             ],
         }
     }
 }
+/// Scrapes the proxies of a [`Provider`].
 pub struct ProviderTask {
     client: Client<HttpsConnector<HttpConnector>>,
     base: Provider,
@@ -86,7 +94,9 @@ impl ProviderTask {
         String::new()
     }
 
-    pub async fn get_proxies(&self) -> Vec<(String, u16, Vec<String>)> {
+    /// Host, port and expected protocols of every proxy on the provider's
+    /// pages. Pages that fail to load are skipped.
+    pub async fn get_proxies(&self) -> Vec<(String, u16, Vec<ProxyProtocol>)> {
         let mut all_proxies = vec![];
         let mut urls = vec![self.base.url.to_string()];
         let mut url_cache = urls.clone();
@@ -115,8 +125,9 @@ impl ProviderTask {
             }
 
             for cap in re.captures_iter(&html) {
-                let ip = cap.get(1).unwrap().as_str();
-                let port = cap.get(2).unwrap().as_str();
+                let ip = cap.name("ip").unwrap().as_str();
+                let ip = ip.trim_start_matches('[').trim_end_matches(']');
+                let port = cap.name("port").unwrap().as_str();
 
                 if let Ok(port) = port.parse::<u16>() {
                     all_proxies.push((ip.to_string(), port, self.base.proto.clone()));
@@ -1,22 +1,50 @@
 pub mod base_provider;
 
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
-use concurrent_queue::ConcurrentQueue;
 use dashmap::DashSet;
 use futures_util::{stream::FuturesUnordered, StreamExt};
-use lazy_static::lazy_static;
 use rand::{seq::SliceRandom, thread_rng};
 use regex::Regex;
-use tokio::sync::Semaphore;
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, BufReader},
+    sync::Semaphore,
+};
 
-use crate::{proxy::Proxy, utils::vec_of_strings};
+use crate::{
+    metrics::{PROVIDER_FETCHES, PROVIDER_PROXIES},
+    proxy::{Credentials, Proxy},
+    store::ProxyStore,
+    types::ProxyProtocol,
+    utils::{host_port, queue::Queue},
+};
 
 use self::base_provider::{Provider, ProviderTask};
 
-lazy_static! {
-    pub static ref PROXIES: ConcurrentQueue<Proxy> = ConcurrentQueue::unbounded();
-    pub static ref UNIQUE_PROXIES: DashSet<String> = DashSet::new();
+/// Proxies waiting to be checked. Each [`Finder`](crate::finder::Finder) has
+/// its own, so that concurrent finders neither take each other's proxies nor
+/// skip the ones already found by another.
+#[derive(Debug)]
+pub struct Candidates {
+    pub proxies: Queue<Proxy>,
+    /// Addresses already queued by providers or the database.
+    unique: DashSet<String>,
+}
+
+impl Candidates {
+    pub fn new() -> Self {
+        Self {
+            proxies: Queue::unbounded(),
+            unique: DashSet::new(),
+        }
+    }
+}
+
+impl Default for Candidates {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub fn providers() -> Vec<Provider> {
@@ -25,7 +53,12 @@ pub fn providers() -> Vec<Provider> {
         Provider {
             name: "free-proxy-list.net",
             url: "https://free-proxy-list.net",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         Provider {
@@ -36,7 +69,12 @@ pub fn providers() -> Vec<Provider> {
         Provider {
             name: "ipaddress.com",
             url: "https://www.ipaddress.com/proxy-list",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         Provider {
@@ -69,31 +107,46 @@ pub fn providers() -> Vec<Provider> {
         Provider {
             name: "www.proxyscan.io/..http",
             url: "https://www.proxyscan.io/download?type=http",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         Provider {
             name: "www.proxyscan.io/..https",
             url: "https://www.proxyscan.io/download?type=https",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         Provider {
             name: "www.proxyscan.io/..socks4",
             url: "https://www.proxyscan.io/download?type=socks4",
-            proto: vec_of_strings!["SOCKS4"],
+            proto: vec![ProxyProtocol::Socks4, ProxyProtocol::Socks4a],
             ..Default::default()
         },
         Provider {
             name: "www.proxyscan.io/..socks5",
             url: "https://www.proxyscan.io/download?type=socks5",
-            proto: vec_of_strings!["SOCKS5"],
+            proto: vec![ProxyProtocol::Socks5],
             ..Default::default()
         },
         Provider {
             name: "openproxylist.xyz",
             url: "https://openproxylist.xyz/http.txt",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         /* proxyspace.pro */
@@ -111,19 +164,24 @@ pub fn providers() -> Vec<Provider> {
         Provider {
             name: "api.proxyscrape.com/..http",
             url: "https://api.proxyscrape.com/?request=getproxies&proxytype=http",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         Provider {
             name: "api.proxyscrape.com/..socks4",
             url: "https://api.proxyscrape.com/?request=getproxies&proxytype=socks4",
-            proto: vec_of_strings!["SOCKS4"],
+            proto: vec![ProxyProtocol::Socks4, ProxyProtocol::Socks4a],
             ..Default::default()
         },
         Provider {
             name: "api.proxyscrape.com/..socks5",
             url: "https://api.proxyscrape.com/?request=getproxies&proxytype=socks5",
-            proto: vec_of_strings!["SOCKS5"],
+            proto: vec![ProxyProtocol::Socks5],
             ..Default::default()
         },
         /* github */
@@ -135,19 +193,24 @@ pub fn providers() -> Vec<Provider> {
         Provider {
             name: "github.com/TheSpeedX/SOCKS-List/http.txt",
             url: "https://raw.githubusercontent.com/TheSpeedX/SOCKS-List/master/http.txt",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         Provider {
             name: "github.com/TheSpeedX/SOCKS-List/socks4.txt",
             url: "https://raw.githubusercontent.com/TheSpeedX/PROXY-List/blob/master/socks4.txt",
-            proto: vec_of_strings!["SOCKS4"],
+            proto: vec![ProxyProtocol::Socks4, ProxyProtocol::Socks4a],
             ..Default::default()
         },
         Provider {
             name: "github.com/TheSpeedX/SOCKS-List/socks5.txt",
             url: "https://raw.githubusercontent.com/TheSpeedX/SOCKS-List/master/socks5.txt",
-            proto: vec_of_strings!["SOCKS5"],
+            proto: vec![ProxyProtocol::Socks5],
             ..Default::default()
         },
         Provider {
@@ -188,7 +251,12 @@ pub fn providers() -> Vec<Provider> {
         Provider {
             name: "github.com/fahimscirex/proxybd/master/proxylist/http.txt",
             url: "https://raw.githubusercontent.com/fahimscirex/proxybd/master/proxylist/http.txt",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         Provider {
@@ -200,13 +268,23 @@ pub fn providers() -> Vec<Provider> {
         Provider {
             name: "github.com/HyperBeats/proxy-list",
             url: "https://raw.githubusercontent.com/HyperBeats/proxy-list/main/http.txt",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         Provider {
             name: "github.com/jetkai/proxy-list/http.txt",
             url: "https://raw.githubusercontent.com/jetkai/proxy-list/main/online-proxies/txt/proxies-http.txt",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         Provider {
@@ -222,19 +300,34 @@ pub fn providers() -> Vec<Provider> {
          Provider {
             name: "github.com/miyukii-chan/proxy-list",
             url: "https://raw.githubusercontent.com/miyukii-chan/proxy-list/master/proxies/http.txt",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         Provider {
             name: "github.com/mmpx12/proxy-list/http.txt",
             url: "https://raw.githubusercontent.com/mmpx12/proxy-list/master/http.txt",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
         Provider {
             name: "github.com/mmpx12/proxy-list/https.txt",
             url: "https://raw.githubusercontent.com/mmpx12/proxy-list/master/https.txt",
-            proto: vec_of_strings!["HTTP", "CONNECT:80", "HTTPS", "CONNECT:25"],
+            proto: vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect25,
+            ],
             ..Default::default()
         },
     ];
@@ -264,35 +357,48 @@ pub fn providers() -> Vec<Provider> {
     providers
 }
 
-async fn update_stack(name: &'static str, proxies: &Vec<(String, u16, Vec<String>)>) {
+async fn update_stack(
+    name: &'static str,
+    proxies: &Vec<(String, u16, Vec<ProxyProtocol>)>,
+    candidates: &Candidates,
+) {
     let mut added = 0;
     for (ip, port, proto) in proxies {
-        let host_port = format!("{}:{}", ip, port);
-        if UNIQUE_PROXIES.get(&host_port).is_some() {
+        let host_port = host_port(ip, *port);
+        if candidates.unique.get(&host_port).is_some() {
             continue;
         }
 
-        if let Some(proxy) = Proxy::create(ip, *port, proto.to_vec()).await {
-            if PROXIES.push(proxy).is_ok() {
+        if let Some(mut proxy) = Proxy::create(ip, *port, proto.to_vec()).await {
+            proxy.provider = Some(name.to_string());
+            if candidates.proxies.push(proxy).is_ok() {
                 added += 1;
-                UNIQUE_PROXIES.insert(host_port);
+                candidates.unique.insert(host_port);
             };
         }
     }
+    PROVIDER_PROXIES
+        .with_label_values(&[name, "new"])
+        .inc_by(added);
     log::debug!("{} of {} proxies added from {}", added, proxies.len(), name);
 }
 
-pub async fn run_all_providers(num_conn: usize) {
+pub async fn run_all_providers(num_conn: usize, candidates: &Arc<Candidates>) {
     let sem = Arc::new(Semaphore::new(num_conn));
     let mut futures = FuturesUnordered::new();
     for provider in providers() {
         let permit = Arc::clone(&sem).acquire_owned().await;
+        let candidates = Arc::clone(candidates);
         futures.push(tokio::spawn(async move {
             let _ = permit;
             let name = provider.name;
             let task = ProviderTask::new(provider);
             let proxies = task.get_proxies().await;
-            update_stack(name, &proxies).await;
+            PROVIDER_FETCHES.with_label_values(&[name]).inc();
+            PROVIDER_PROXIES
+                .with_label_values(&[name, "scraped"])
+                .inc_by(proxies.len() as u64);
+            update_stack(name, &proxies, &candidates).await;
         }));
     }
 
@@ -300,3 +406,66 @@ pub async fn run_all_providers(num_conn: usize) {
         continue;
     }
 }
+
+/// Queues the proxies that passed their last check in a previous run.
+pub async fn handle_store_input(store: &ProxyStore, candidates: &Candidates) {
+    let known_good = match store.known_good() {
+        Ok(known_good) => known_good,
+        Err(err) => {
+            log::error!("Failed to read the proxy database: {}", err);
+            return;
+        }
+    };
+    let mut added = 0;
+    for stored in known_good {
+        let host_port = host_port(&stored.host, stored.port);
+        if !candidates.unique.insert(host_port) {
+            continue;
+        }
+        if let Some(mut proxy) = Proxy::create(&stored.host, stored.port, stored.types).await {
+            proxy.hostname = stored.hostname.or(proxy.hostname);
+            proxy.credentials = stored.credentials;
+            proxy.provider = stored.provider;
+            if candidates.proxies.push(proxy).is_ok() {
+                added += 1;
+            }
+        }
+    }
+    log::info!("{} known good proxies loaded from the database", added);
+}
+
+pub async fn handle_file_input(files: Vec<PathBuf>, candidates: &Candidates) {
+    let ip_port = Regex::new(
+        r#"(?:(?P<user>[^:@\s]+):(?P<pass>[^@\s]*)@)?(?P<ip>(?:\d+\.?){4}|\[[0-9a-fA-F:.]+\]|[a-zA-Z0-9][a-zA-Z0-9.-]*\.[a-zA-Z]{2,}):(?P<port>\d+)"#,
+    )
+    .unwrap();
+    for file in files {
+        let provider = file.display().to_string();
+        match File::open(&file).await {
+            Ok(file) => {
+                let buffer = BufReader::new(file);
+                let mut lines = buffer.lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if let Some(cap) = ip_port.captures(&line) {
+                        let ip = cap.name("ip").unwrap().as_str();
+                        let port = cap.name("port").unwrap().as_str();
+
+                        if let Ok(port) = port.parse::<u16>() {
+                            if let Some(mut proxy) = Proxy::create(ip, port, vec![]).await {
+                                if let (Some(user), Some(pass)) =
+                                    (cap.name("user"), cap.name("pass"))
+                                {
+                                    proxy.credentials =
+                                        Some(Credentials::new(user.as_str(), pass.as_str()));
+                                }
+                                proxy.provider = Some(provider.clone());
+                                candidates.proxies.push(proxy).unwrap()
+                            }
+                        };
+                    }
+                }
+            }
+            Err(e) => log::error!("{}: {:?}", e, file),
+        }
+    }
+}
@@ -2,15 +2,17 @@ use std::{
     cmp::Ordering,
     collections::BTreeMap,
     fmt::Debug,
-    io::{Error, ErrorKind, Result},
-    net::IpAddr,
+    io::{Error, Result},
+    net::{IpAddr, SocketAddr},
     pin::Pin,
     str::from_utf8,
     task::{Context, Poll},
     time::Duration,
 };
 
+use base64::{engine::general_purpose::STANDARD, Engine};
 use native_tls::TlsConnector;
+use serde::{Deserialize, Serialize};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
     net::TcpStream,
@@ -19,9 +21,14 @@ use tokio::{
 use tokio_native_tls::TlsStream;
 
 use crate::{
+    error::ProxyError,
+    metrics::CHECK_ERRORS,
     resolver::{GeoData, Resolver},
+    types::{AnonymityLevel, ProxyProtocol},
     utils::{
+        host_port,
         http::response::ResponseParser,
+        net::connect_any,
         serializer::{Country, Geo, ProxyData, ProxyType, Region},
     },
 };
@@ -36,35 +43,92 @@ fn bytes_to_string(bytes: &[u8]) -> String {
     }
 }
 
+/// Username and password used to authenticate against a proxy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    /// Value of the `Proxy-Authorization` header for Basic auth.
+    pub fn basic_auth(&self) -> String {
+        let token = STANDARD.encode(format!("{}:{}", self.username, self.password));
+        format!("Basic {}", token)
+    }
+
+    /// Parses a `Proxy-Authorization` header value for Basic auth.
+    pub fn from_basic_auth(value: &str) -> Option<Self> {
+        let (scheme, token) = value.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let decoded = String::from_utf8(STANDARD.decode(token.trim()).ok()?).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+        Some(Self::new(username, password))
+    }
+}
+
+/// A proxy, the connection it is checked over and the outcome of its checks.
 #[derive(Debug)]
 pub struct Proxy {
+    /// Ip address of the proxy.
     pub host: String,
     pub port: u16,
-    pub expected_types: Vec<String>,
+    /// Original hostname when the proxy was not given as an ip address.
+    pub hostname: Option<String>,
+    pub credentials: Option<Credentials>,
+    /// Name of the provider or file the proxy was found in.
+    pub provider: Option<String>,
+    /// Protocols the proxy is expected to support, as given by its source.
+    pub expected_types: Vec<ProxyProtocol>,
     pub geo: GeoData,
-    pub types: Vec<(String, Option<String>)>,
-    pub schemes: Vec<String>,
-    pub logs: Vec<(String, String, Duration)>,
-    pub negotiator_proto: String,
+    /// Protocols that passed the checks, with the anonymity level for HTTP.
+    pub types: Vec<(ProxyProtocol, Option<AnonymityLevel>)>,
+    /// Schemes the proxy serves in `serve` mode, see [`Proxy::get_schemes`].
+    pub schemes: Vec<ProxyProtocol>,
+    /// Steps of the checks, by protocol, with their runtime.
+    pub logs: Vec<(ProxyProtocol, String, Duration)>,
+    /// Protocol being checked.
+    pub negotiator_proto: ProxyProtocol,
 
     pub verify_ssl: bool,
+    /// Seconds each connection, send and receive may take.
     pub timeout: i32,
+    /// Runtimes of the check steps in seconds.
     pub runtimes: Vec<f64>,
 
     pub tcp_stream: Option<TcpStream>,
     pub tls_stream: Option<TlsStream<TcpStream>>,
 
+    /// Number of connections made to the proxy.
     pub request_stat: i32,
+    /// Number of errors, by [`ProxyError::kind`].
     pub error_stat: BTreeMap<String, i32>,
 
+    /// Whether the last check passed.
     pub is_working: bool,
 }
 
 impl Proxy {
-    pub async fn create(host: &str, port: u16, expected_types: Vec<String>) -> Option<Self> {
-        let mut host = host.to_string();
+    /// Proxy at `host:port` with its geo data. A hostname is resolved first,
+    /// `None` when it does not resolve.
+    pub async fn create(host: &str, port: u16, expected_types: Vec<ProxyProtocol>) -> Option<Self> {
+        let mut host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
         let resolver = Resolver::new();
+        let mut hostname = None;
         if !resolver.host_is_ip(&host) {
+            hostname = Some(host.clone());
             host = resolver.resolve(host).await;
         }
         if let Ok(ip_address) = host.parse::<IpAddr>() {
@@ -73,12 +137,15 @@ impl Proxy {
             return Some(Proxy {
                 host,
                 port,
+                hostname,
+                credentials: None,
+                provider: None,
                 expected_types,
                 geo,
                 types: vec![],
                 schemes: vec![],
                 logs: vec![],
-                negotiator_proto: "HTTP".to_string(),
+                negotiator_proto: ProxyProtocol::Http,
                 timeout: 5,
                 runtimes: vec![],
                 tcp_stream: None,
@@ -92,6 +159,7 @@ impl Proxy {
         None
     }
 
+    /// Errors per connection.
     pub fn error_rate(&self) -> f64 {
         if self.request_stat == 0 {
             return 0.0;
@@ -100,6 +168,7 @@ impl Proxy {
         sum / self.request_stat as f64
     }
 
+    /// Average runtime of the check steps in seconds.
     pub fn avg_resp_time(&self) -> f64 {
         if self.runtimes.is_empty() {
             return 0.0;
@@ -108,36 +177,50 @@ impl Proxy {
         sum / self.runtimes.len() as f64
     }
 
+    /// Sort key, lower is better.
     pub fn priority(&self) -> (f64, f64) {
         (self.error_rate(), self.avg_resp_time())
     }
 
-    pub fn get_schemes(&mut self) -> Vec<String> {
+    /// Schemes of the working protocols, computed once.
+    pub fn get_schemes(&mut self) -> Vec<ProxyProtocol> {
         if self.schemes.is_empty() {
             for (proxy_type, _) in &self.types {
-                if !self.schemes.contains(&"HTTP".to_string())
-                    && ["HTTP", "CONNECT:80", "SOCKS4", "SOCKS5"].contains(&proxy_type.as_str())
-                {
-                    self.schemes.push("HTTP".to_string());
-                }
-                if !self.schemes.contains(&"HTTPS".to_string())
-                    && ["HTTPS", "SOCKS4", "SOCKS5"].contains(&proxy_type.as_str())
-                {
-                    self.schemes.push("HTTPS".to_string());
+                for scheme in proxy_type.schemes() {
+                    if !self.schemes.contains(&scheme) {
+                        self.schemes.push(scheme);
+                    }
                 }
             }
         }
         self.schemes.clone()
     }
 
+    /// `Proxy-Authorization` header line, empty when the proxy has no credentials.
+    pub fn auth_header(&self) -> String {
+        match &self.credentials {
+            Some(credentials) => format!("Proxy-Authorization: {}\r\n", credentials.basic_auth()),
+            None => String::new(),
+        }
+    }
+
+    /// `host:port`, with the original hostname if any.
     pub fn as_text(&self) -> String {
-        format!("{}:{}", self.host, self.port)
+        host_port(self.hostname.as_ref().unwrap_or(&self.host), self.port)
     }
 
+    /// [`Proxy::as_data`] as json.
     pub fn as_json(&self) -> String {
-        let proxy_data = ProxyData {
+        serde_json::to_string(&self.as_data()).unwrap()
+    }
+
+    /// What is reported about the proxy, see [`ProxyData`].
+    pub fn as_data(&self) -> ProxyData {
+        ProxyData {
             host: self.host.clone(),
+            hostname: self.hostname.clone(),
             port: self.port,
+            credentials: self.credentials.clone(),
             geo: Geo {
                 country: Country {
                     code: self.geo.iso_code.clone(),
@@ -157,12 +240,11 @@ impl Proxy {
                 .collect(),
             avg_resp_time: self.avg_resp_time(),
             error_rate: self.error_rate(),
-        };
-
-        serde_json::to_string(&proxy_data).unwrap()
+        }
     }
 
-    pub fn log(&mut self, msg: &str, stime: Option<Duration>, error: Option<String>) {
+    /// Adds a step of the current check to `logs`, counting its runtime.
+    pub fn log(&mut self, msg: &str, stime: Option<Duration>) {
         let runtime = if let Some(stime) = stime {
             self.runtimes.push(stime.as_secs_f64());
             stime
@@ -170,32 +252,33 @@ impl Proxy {
             Duration::from_micros(0)
         };
         log::debug!(
-            "{}:{} [{}] {}, Runtime {:?}",
-            self.host,
-            self.port,
+            "{} [{}] {}, Runtime {:?}",
+            self.as_text(),
             self.negotiator_proto,
             msg,
             runtime
         );
 
         self.logs
-            .push((self.negotiator_proto.clone(), msg.to_string(), runtime));
+            .push((self.negotiator_proto, msg.to_string(), runtime));
+    }
 
-        if let Some(error) = error {
-            if !self.error_stat.contains_key(&error) {
-                self.error_stat.insert(error.clone(), 0);
-            }
-            if let Some(value) = self.error_stat.get_mut(&error) {
-                *value += 1
-            }
-        }
+    /// Logs `error` and counts it in `error_stat`.
+    pub fn log_error(&mut self, error: ProxyError, stime: Option<Duration>) {
+        self.log(error.to_string().as_str(), stime);
+        CHECK_ERRORS
+            .with_label_values(&[self.negotiator_proto.as_str(), error.kind()])
+            .inc();
+        *self.error_stat.entry(error.kind().to_string()).or_insert(0) += 1;
     }
 
+    /// Opens the tcp connection the checks are run over.
     pub async fn connect(&mut self) -> bool {
         self.tcp_stream = self.connect_tcp().await;
         self.tcp_stream.is_some()
     }
 
+    /// Writes `body` to the open connection.
     pub async fn send(&mut self, body: &[u8]) -> bool {
         let stime = Instant::now();
         match self.write_all(body).await {
@@ -203,21 +286,17 @@ impl Proxy {
                 self.log(
                     format!("Sending {} bytes: {}", body.len(), bytes_to_string(body)).as_str(),
                     Some(stime.elapsed()),
-                    None,
                 );
                 true
             }
             Err(e) => {
-                self.log(
-                    format!("Sending error: {}", e).as_str(),
-                    Some(stime.elapsed()),
-                    Some(e.to_string()),
-                );
+                self.log_error(ProxyError::Send(e), Some(stime.elapsed()));
                 false
             }
         }
     }
 
+    /// Reads exactly `size` bytes from the open connection.
     pub async fn recv(&mut self, size: usize) -> Option<Vec<u8>> {
         let stime = Instant::now();
         let mut chunk = vec![0; size];
@@ -239,26 +318,18 @@ impl Proxy {
                             )
                             .as_str(),
                             Some(stime.elapsed()),
-                            None,
                         );
                         return Some(chunk.to_vec());
                     }
                 }
-                Err(e) => self.log(
-                    format!("Failed to receive: {}", e).as_str(),
-                    Some(stime.elapsed()),
-                    Some(e.to_string()),
-                ),
+                Err(e) => self.log_error(ProxyError::Recv(e), Some(stime.elapsed())),
             },
-            Err(e) => self.log(
-                format!("Received timeout: {}", e).as_str(),
-                Some(stime.elapsed()),
-                Some(e.to_string()),
-            ),
+            Err(_) => self.log_error(ProxyError::RecvTimeout, Some(stime.elapsed())),
         }
         None
     }
 
+    /// Reads until the connection is closed or times out.
     pub async fn recv_all(&mut self) -> Option<Vec<u8>> {
         let stime = Instant::now();
         let mut chunk = vec![0; 1024];
@@ -279,22 +350,13 @@ impl Proxy {
                         buf.extend(data)
                     }
                     Err(e) => {
-                        self.log(
-                            format!("Failed to receive: {}", e).as_str(),
-                            Some(stime.elapsed()),
-                            Some(e.to_string()),
-                        );
+                        self.log_error(ProxyError::Recv(e), Some(stime.elapsed()));
                         break;
                     }
                 },
 
-                Err(e) => {
-                    self.log(
-                        format!("Received timeout: {}", e).as_str(),
-                        Some(stime.elapsed()),
-                        Some(e.to_string()),
-                    );
-                    // TODO: log error
+                Err(_) => {
+                    self.log_error(ProxyError::RecvTimeout, Some(stime.elapsed()));
                     break;
                 }
             }
@@ -307,11 +369,11 @@ impl Proxy {
             )
             .as_str(),
             Some(stime.elapsed()),
-            None,
         );
         Some(buf)
     }
 
+    /// Shuts the open connections down.
     pub async fn close(&mut self) {
         if self.tcp_stream.is_some() {
             self.close_tls().await;
@@ -363,12 +425,11 @@ impl std::fmt::Display for Proxy {
 
         write!(
             f,
-            "<Proxy {} {:.2}s [{}] {}:{}>",
+            "<Proxy {} {:.2}s [{}] {}>",
             self.geo.iso_code,
             self.avg_resp_time(),
             types.join(", "),
-            self.host,
-            self.port
+            self.as_text()
         )
     }
 }
@@ -384,7 +445,7 @@ impl AsyncRead for Proxy {
         } else if let Some(ref mut tls_stream) = self.tls_stream {
             Pin::new(tls_stream).poll_read(cx, buf)
         } else {
-            Poll::Ready(Err(Error::new(ErrorKind::Other, "No Stream Available")))
+            Poll::Ready(Err(Error::other("No Stream Available")))
         }
     }
 }
@@ -400,7 +461,7 @@ impl AsyncWrite for Proxy {
         } else if let Some(ref mut tls_stream) = self.tls_stream {
             Pin::new(tls_stream).poll_write(cx, buf)
         } else {
-            Poll::Ready(Err(Error::new(ErrorKind::Other, "No Stream Available")))
+            Poll::Ready(Err(Error::other("No Stream Available")))
         }
     }
 
@@ -410,7 +471,7 @@ impl AsyncWrite for Proxy {
         } else if let Some(ref mut tls_stream) = self.tls_stream {
             Pin::new(tls_stream).poll_flush(cx)
         } else {
-            Poll::Ready(Err(Error::new(ErrorKind::Other, "No Stream Available")))
+            Poll::Ready(Err(Error::other("No Stream Available")))
         }
     }
 
@@ -420,7 +481,7 @@ impl AsyncWrite for Proxy {
         } else if let Some(ref mut tls_stream) = self.tls_stream {
             Pin::new(tls_stream).poll_shutdown(cx)
         } else {
-            Poll::Ready(Err(Error::new(ErrorKind::Other, "No Stream Available")))
+            Poll::Ready(Err(Error::other("No Stream Available")))
         }
     }
 }
@@ -429,44 +490,48 @@ impl AsyncWrite for Proxy {
 impl Proxy {
     async fn connect_tcp(&mut self) -> Option<TcpStream> {
         let stime = Instant::now();
-        self.log("Initial connection", Some(stime.elapsed()), None);
-        match timeout(
-            Duration::from_secs(self.timeout as u64),
-            TcpStream::connect(self.as_text()),
-        )
-        .await
-        {
+        self.log("Initial connection", Some(stime.elapsed()));
+        let addrs = self.socket_addrs().await;
+        match timeout(Duration::from_secs(self.timeout as u64), connect_any(addrs)).await {
             Ok(stream) => match stream {
                 Ok(stream) => {
-                    self.log("Connection success", Some(stime.elapsed()), None);
+                    self.log("Connection success", Some(stime.elapsed()));
                     self.request_stat += 1;
                     Some(stream)
                 }
                 Err(e) => {
-                    self.log(
-                        format!("Connection error: {}", e).as_str(),
-                        Some(stime.elapsed()),
-                        Some(e.to_string()),
-                    );
+                    self.log_error(ProxyError::connect(e), Some(stime.elapsed()));
                     None
                 }
             },
-            Err(e) => {
-                self.log("Connection timeout", None, Some(e.to_string()));
+            Err(_) => {
+                self.log_error(ProxyError::ConnectTimeout, None);
                 None
             }
         }
     }
 
+    /// Addresses to connect to, re-resolving the hostname if there is one.
+    async fn socket_addrs(&self) -> Vec<SocketAddr> {
+        let mut ips = vec![];
+        if let Some(hostname) = &self.hostname {
+            ips = Resolver::new().resolve_all(hostname).await;
+        }
+        if ips.is_empty() {
+            if let Ok(ip) = self.host.parse::<IpAddr>() {
+                ips.push(ip);
+            }
+        }
+        ips.into_iter()
+            .map(|ip| SocketAddr::new(ip, self.port))
+            .collect()
+    }
+
     async fn close_tcp(&mut self) {
         if let Some(stream) = self.tcp_stream.as_mut() {
             match stream.shutdown().await {
-                Ok(_) => self.log("Connection closed", None, None),
-                Err(e) => self.log(
-                    format!("Failed to close connection: {}", e).as_str(),
-                    None,
-                    Some(e.to_string()),
-                ),
+                Ok(_) => self.log("Connection closed", None),
+                Err(e) => self.log_error(ProxyError::Close(e), None),
             }
         }
         self.tcp_stream = None;
@@ -494,14 +559,9 @@ impl Proxy {
                 )
                 .as_str(),
                 Some(stime_send.elapsed()),
-                None,
             ),
             Err(e) => {
-                self.log(
-                    format!("Sending error: {}", e).as_str(),
-                    Some(stime_send.elapsed()),
-                    Some(e.to_string()),
-                );
+                self.log_error(ProxyError::Send(e), Some(stime_send.elapsed()));
                 return false;
             }
         };
@@ -526,36 +586,35 @@ impl Proxy {
                             )
                             .as_str(),
                             Some(stime_recv.elapsed()),
-                            None,
                         );
                     }
                 }
                 Err(e) => {
-                    self.log(
-                        format!("Failed to receive: {}", e).as_str(),
-                        Some(stime_recv.elapsed()),
-                        Some(e.to_string()),
-                    );
+                    self.log_error(ProxyError::Recv(e), Some(stime_recv.elapsed()));
                     return false;
                 }
             },
-            Err(e) => {
-                self.log(
-                    format!("Received timeout: {}", e).as_str(),
-                    Some(stime_recv.elapsed()),
-                    Some(e.to_string()),
-                );
+            Err(_) => {
+                self.log_error(ProxyError::RecvTimeout, Some(stime_recv.elapsed()));
                 return false;
             }
         }
 
         let response = ResponseParser::parse(&chunk);
-        if response.status_code.unwrap_or(0) != 200 {
-            return false;
+        match response.status_code.unwrap_or(0) {
+            200 => {}
+            407 => {
+                self.log_error(ProxyError::AuthRequired, None);
+                return false;
+            }
+            code => {
+                self.log_error(ProxyError::BadStatus(code), None);
+                return false;
+            }
         }
 
         let stime = Instant::now();
-        self.log("SSL: Initial connection", Some(stime.elapsed()), None);
+        self.log("SSL: Initial connection", Some(stime.elapsed()));
 
         let config = TlsConnector::builder()
             .danger_accept_invalid_certs(!self.verify_ssl)
@@ -570,24 +629,22 @@ impl Proxy {
         {
             Ok(stream) => match stream {
                 Ok(stream) => {
-                    self.log("SSL: Connection success", Some(stime.elapsed()), None);
+                    self.log("SSL: Connection success", Some(stime.elapsed()));
                     self.request_stat += 1;
                     Some(stream)
                 }
                 Err(e) => {
-                    self.log(
-                        format!("SSL: Connection error: {}", e).as_str(),
+                    self.log_error(
+                        ProxyError::TlsHandshake(e.to_string()),
                         Some(stime.elapsed()),
-                        Some(e.to_string()),
                     );
                     None
                 }
             },
             Err(e) => {
-                self.log(
-                    "SSL: Connection timeout",
+                self.log_error(
+                    ProxyError::TlsHandshake(e.to_string()),
                     Some(stime.elapsed()),
-                    Some(e.to_string()),
                 );
                 None
             }
@@ -599,12 +656,8 @@ impl Proxy {
     async fn close_tls(&mut self) {
         if let Some(stream) = self.tls_stream.as_mut() {
             match stream.shutdown().await {
-                Ok(_) => self.log("SSL: Connection closed", None, None),
-                Err(e) => self.log(
-                    format!("SSL: Failed to close connection: {}", e).as_str(),
-                    None,
-                    Some(e.to_string()),
-                ),
+                Ok(_) => self.log("SSL: Connection closed", None),
+                Err(e) => self.log_error(ProxyError::Close(e), None),
             }
         }
         self.tcp_stream = None;
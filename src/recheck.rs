@@ -0,0 +1,119 @@
+//! Differences between two checks of the same proxy, see [`Finder::recheck`].
+//!
+//! [`Finder::recheck`]: crate::finder::Finder::recheck
+
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    types::{AnonymityLevel, ProxyProtocol},
+    utils::{
+        host_port,
+        serializer::{ProxyData, ProxyType},
+    },
+};
+
+/// How a proxy changed since its previous check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Died,
+    CameBack,
+    /// Still working, with other protocols or anonymity levels.
+    Changed,
+    Unchanged,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Died => "died",
+            ChangeKind::CameBack => "came back",
+            ChangeKind::Changed => "changed",
+            ChangeKind::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// A proxy before and after a recheck. A proxy that does not work has no types.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub previous: ProxyData,
+    pub current: ProxyData,
+}
+
+impl Change {
+    pub fn new(previous: ProxyData, current: ProxyData) -> Self {
+        let kind = match (previous.types.is_empty(), current.types.is_empty()) {
+            (false, true) => ChangeKind::Died,
+            (true, false) => ChangeKind::CameBack,
+            _ if sorted_types(&previous.types) == sorted_types(&current.types) => {
+                ChangeKind::Unchanged
+            }
+            _ => ChangeKind::Changed,
+        };
+        Self {
+            kind,
+            previous,
+            current,
+        }
+    }
+
+    pub fn as_json(&self) -> String {
+        json!({
+            "host": self.current.host,
+            "hostname": self.current.hostname,
+            "port": self.current.port,
+            "change": self.kind,
+            "before": self.previous.types,
+            "after": self.current.types,
+        })
+        .to_string()
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let host = self.current.hostname.as_ref().unwrap_or(&self.current.host);
+        write!(
+            f,
+            "{:<9} {} ",
+            self.kind.as_str(),
+            host_port(host, self.current.port)
+        )?;
+        match self.kind {
+            ChangeKind::Died => write!(f, "[{}]", format_types(&self.previous.types)),
+            ChangeKind::Changed => write!(
+                f,
+                "[{}] -> [{}]",
+                format_types(&self.previous.types),
+                format_types(&self.current.types)
+            ),
+            _ => write!(f, "[{}]", format_types(&self.current.types)),
+        }
+    }
+}
+
+fn sorted_types(types: &[ProxyType]) -> Vec<(ProxyProtocol, Option<AnonymityLevel>)> {
+    let mut types: Vec<_> = types
+        .iter()
+        .map(|proxy_type| (proxy_type.proxy_type, proxy_type.level))
+        .collect();
+    types.sort();
+    types.dedup();
+    types
+}
+
+fn format_types(types: &[ProxyType]) -> String {
+    sorted_types(types)
+        .into_iter()
+        .map(|(proto, level)| match level {
+            Some(level) => format!("{}: {}", proto, level),
+            None => proto.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
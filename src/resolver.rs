@@ -10,8 +10,12 @@ use trust_dns_resolver::{
     TokioAsyncResolver,
 };
 
-use crate::utils::{geolite_database::open_geolite_db, http::hyper_client};
+use crate::{
+    error::ProxyError,
+    utils::{geolite_database::open_geolite_db, http::hyper_client},
+};
 
+/// Location of an ip address, `unknown` when it is not in the database.
 #[derive(Debug, Clone)]
 pub struct GeoData {
     pub iso_code: String,
@@ -41,7 +45,7 @@ lazy_static! {
         AsyncOnce::new(async { open_geolite_db().await.unwrap() });
     pub static ref CACHED_HOSTS: Arc<Mutex<HashMap<String, String>>> =
         Arc::new(Mutex::new(HashMap::new()));
-    pub static ref EXT_IP_HOSTS: Vec<String> = vec![
+    pub static ref EXT_IP_HOSTS: Vec<String> = [
         "https://wtfismyip.com/text",
         "http://api.ipify.org/",
         "http://ipinfo.io/ip",
@@ -55,7 +59,9 @@ lazy_static! {
     .collect();
 }
 
-#[derive(Debug, Clone)]
+/// Resolves hostnames and looks ip addresses up in the GeoLite2 database.
+/// Resolved hosts are cached for the whole process.
+#[derive(Debug, Clone, Default)]
 pub struct Resolver;
 
 impl Resolver {
@@ -63,14 +69,13 @@ impl Resolver {
         Resolver {}
     }
 
+    /// Whether `ipv4` is an ip address, of either version.
     pub fn host_is_ip(&self, ipv4: &str) -> bool {
-        let ipaddress: Option<IpAddr> = match ipv4.parse() {
-            Ok(ip) => Some(ip),
-            Err(_) => None,
-        };
+        let ipaddress: Option<IpAddr> = ipv4.parse().ok();
         ipaddress.is_some()
     }
 
+    /// Country, region and city of `ip_address`.
     pub async fn get_ip_info(&self, ip_address: IpAddr) -> GeoData {
         let mut geodata = GeoData::default();
         if let Ok(lookup) = GEO_CITY.get().await.lookup::<City>(ip_address) {
@@ -118,6 +123,7 @@ impl Resolver {
         geodata
     }
 
+    /// First address of `host`, or `host` itself when it does not resolve.
     pub async fn resolve(&self, host: String) -> String {
         if let Some(cached_host) = CACHED_HOSTS.lock().unwrap().get(&host) {
             log::debug!("Host {} is already cached, returning", host);
@@ -138,7 +144,19 @@ impl Resolver {
         host
     }
 
-    pub async fn get_real_ext_ip(&self) -> String {
+    /// Resolves every address of `host`, bypassing the cache.
+    pub async fn resolve_all(&self, host: &str) -> Vec<IpAddr> {
+        match DNS_RESOLVER.lookup_ip(host).await {
+            Ok(response) => response.iter().collect(),
+            Err(e) => {
+                log::debug!("Failed to resolve: {}, {}", host, e);
+                vec![]
+            }
+        }
+    }
+
+    /// External ip address of this host, asked to several public services.
+    pub async fn get_real_ext_ip(&self) -> Result<String, ProxyError> {
         let client = hyper_client();
         for ext_ip_host in EXT_IP_HOSTS.iter() {
             let request = Request::builder()
@@ -153,7 +171,7 @@ impl Resolver {
                         let ip = body_str.trim();
                         if self.host_is_ip(ip) {
                             log::debug!("Ext ip ({}) retrieved using host: {}", ip, ext_ip_host);
-                            return ip.to_string();
+                            return Ok(ip.to_string());
                         }
                     }
                     Err(e) => log::error!("{}", e),
@@ -162,6 +180,6 @@ impl Resolver {
             }
         }
 
-        std::process::exit(0);
+        Err(ProxyError::NoExternalIp)
     }
 }
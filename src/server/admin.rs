@@ -0,0 +1,224 @@
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+    server::conn::Http,
+    service::service_fn,
+    Body, Method, Request, Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::net::TcpListener;
+
+use super::{
+    client::Client,
+    proxy_pool::{SimpleProxy, LIVE_PROXIES},
+    Server, POOL,
+};
+use crate::{
+    checker::working_judges, metrics, proxy::Credentials, types::ProxyProtocol,
+    utils::serializer::ProxyType,
+};
+
+/// Stats of an upstream as reported by the admin API.
+#[derive(Debug, Serialize)]
+struct ProxyStats<'a> {
+    host: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostname: Option<&'a str>,
+    port: u16,
+    country: &'a str,
+    types: Vec<ProxyType>,
+    requests: i32,
+    errors: &'a BTreeMap<String, i32>,
+    error_rate: f64,
+    avg_resp_time: f64,
+    connections: usize,
+    /// Seconds since the proxy was last taken.
+    idle: Option<f64>,
+}
+
+impl<'a> From<&'a SimpleProxy> for ProxyStats<'a> {
+    fn from(proxy: &'a SimpleProxy) -> Self {
+        Self {
+            host: &proxy.host,
+            hostname: proxy.hostname.as_deref(),
+            port: proxy.port,
+            country: &proxy.geo.iso_code,
+            types: proxy
+                .types
+                .iter()
+                .map(|&(proxy_type, level)| ProxyType { proxy_type, level })
+                .collect(),
+            requests: proxy.request_stat,
+            errors: &proxy.error_stat,
+            error_rate: proxy.error_rate(),
+            avg_resp_time: proxy.avg_resp_time(),
+            connections: proxy.connections,
+            idle: proxy.last_used.map(|used| used.elapsed().as_secs_f64()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ProxyAddr {
+    host: String,
+    port: u16,
+}
+
+#[derive(Debug, Deserialize)]
+struct NewProxy {
+    host: String,
+    port: u16,
+    types: Vec<ProxyProtocol>,
+}
+
+/// Serves the admin API forever. Clients go through the same allowlist as
+/// the proxy listener and, when users are configured, authenticate as one of
+/// them with Basic `Authorization`. Without users the pool can not be changed.
+pub async fn accept_loop(server: Arc<Server>, listener: TcpListener) {
+    loop {
+        if let Ok((stream, addr)) = listener.accept().await {
+            if !server.is_allowed(&Client::new(addr)) {
+                log::warn!("Rejected admin client {}", addr);
+                continue;
+            }
+            let server = Arc::clone(&server);
+            tokio::task::spawn(async move {
+                if let Err(err) = Http::new()
+                    .serve_connection(
+                        stream,
+                        service_fn(move |request| handle(Arc::clone(&server), addr, request)),
+                    )
+                    .await
+                {
+                    log::error!("Admin connection error: {}", err);
+                }
+            });
+        }
+    }
+}
+
+async fn handle(
+    server: Arc<Server>,
+    addr: SocketAddr,
+    request: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    log::debug!(
+        "Admin request from {}: {} {}",
+        addr,
+        request.method(),
+        request.uri()
+    );
+    let mut client = Client::new(addr);
+    client.credentials = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Credentials::from_basic_auth);
+    if !server.users.is_empty() && !server.is_user(&client) {
+        log::warn!("Unauthorized admin client {}", addr);
+        let mut response = error_response(StatusCode::UNAUTHORIZED, "authentication required");
+        response.headers_mut().insert(
+            WWW_AUTHENTICATE,
+            "Basic realm=\"proxy-rs admin\"".parse().unwrap(),
+        );
+        return Ok(response);
+    }
+    if server.users.is_empty() && request.method() == Method::POST {
+        log::warn!("Refused admin change from {} without users", addr);
+        return Ok(error_response(
+            StatusCode::FORBIDDEN,
+            "changing the pool requires users, see --auth",
+        ));
+    }
+    let method = request.method().clone();
+    let path = request.uri().path().trim_end_matches('/').to_string();
+    let body = hyper::body::to_bytes(request.into_body()).await?;
+
+    let response = match (method, path.as_str()) {
+        (Method::GET, "/status") => {
+            let pool = POOL.lock();
+            json_response(
+                StatusCode::OK,
+                json!({
+                    "live_proxies": LIVE_PROXIES.len(),
+                    "pool": pool.pool().len(),
+                    "newcomers": pool.newcomers().count(),
+                    "judges": working_judges(),
+                }),
+            )
+        }
+        (Method::GET, "/metrics") => metrics::response(),
+        (Method::GET, "/proxies") => {
+            let pool = POOL.lock();
+            json_response(
+                StatusCode::OK,
+                json!({
+                    "pool": pool.pool().iter().map(ProxyStats::from).collect::<Vec<_>>(),
+                    "newcomers": pool.newcomers().map(ProxyStats::from).collect::<Vec<_>>(),
+                }),
+            )
+        }
+        (Method::POST, "/proxies") => match parse_new_proxy(&body) {
+            Ok(new) => {
+                let mut pool = POOL.lock();
+                if pool.contains(&new.host, new.port) {
+                    error_response(StatusCode::CONFLICT, "proxy is already in the pool")
+                } else if pool.is_banned(&new.host, new.port) {
+                    error_response(StatusCode::FORBIDDEN, "proxy is banned")
+                } else {
+                    let proxy = SimpleProxy::new(&new.host, new.port, new.types);
+                    let response = json_response(StatusCode::CREATED, ProxyStats::from(&proxy));
+                    log::info!("{} added by admin", proxy.as_text());
+                    pool.put(proxy);
+                    response
+                }
+            }
+            Err(err) => error_response(StatusCode::BAD_REQUEST, &err),
+        },
+        (Method::POST, "/proxies/remove") | (Method::POST, "/proxies/ban") => {
+            match serde_json::from_slice::<ProxyAddr>(&body) {
+                Ok(addr) => {
+                    let mut pool = POOL.lock();
+                    let removed = if path.ends_with("/ban") {
+                        log::info!("{}:{} banned by admin", addr.host, addr.port);
+                        pool.ban(&addr.host, addr.port)
+                    } else {
+                        pool.remove(&addr.host, addr.port)
+                    };
+                    match removed {
+                        Some(proxy) => json_response(StatusCode::OK, ProxyStats::from(&proxy)),
+                        None if path.ends_with("/ban") => {
+                            json_response(StatusCode::OK, json!(null))
+                        }
+                        None => error_response(StatusCode::NOT_FOUND, "proxy is not in the pool"),
+                    }
+                }
+                Err(err) => error_response(StatusCode::BAD_REQUEST, &err.to_string()),
+            }
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "not found"),
+    };
+    Ok(response)
+}
+
+fn parse_new_proxy(body: &[u8]) -> Result<NewProxy, String> {
+    let new = serde_json::from_slice::<NewProxy>(body).map_err(|e| e.to_string())?;
+    if new.types.is_empty() {
+        return Err("types must not be empty".to_string());
+    }
+    Ok(new)
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&value).unwrap()))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, json!({ "error": message }))
+}
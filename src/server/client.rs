@@ -0,0 +1,140 @@
+use std::{
+    fs, io,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+
+use hyper::{header::PROXY_AUTHORIZATION, HeaderMap};
+use ipnet::IpNet;
+
+use super::{selector::Selector, Server};
+use crate::proxy::Credentials;
+
+/// Header a client sets to keep the same upstream across requests.
+pub const SESSION_HEADER: &str = "x-proxy-session";
+
+/// What is known about the client of a request.
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub addr: SocketAddr,
+    /// Credentials from `Proxy-Authorization` or the SOCKS5 sub-negotiation.
+    pub credentials: Option<Credentials>,
+    /// Value of the `X-Proxy-Session` header.
+    pub session: Option<String>,
+}
+
+impl Client {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            credentials: None,
+            session: None,
+        }
+    }
+
+    pub fn from_request(addr: SocketAddr, headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        Self {
+            addr,
+            credentials: header(PROXY_AUTHORIZATION.as_str())
+                .and_then(Credentials::from_basic_auth),
+            session: header(SESSION_HEADER).map(str::to_string),
+        }
+    }
+}
+
+impl Server {
+    /// Whether the ip address of `client` passes the allowlist and denylist.
+    pub fn is_allowed(&self, client: &Client) -> bool {
+        let ip = client.addr.ip().to_canonical();
+        !self.deny.iter().any(|net| net.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+    }
+
+    /// Whether `client` sent the credentials of a user, when users are configured.
+    ///
+    /// Selectors may follow the username after an underscore, as in
+    /// `alice_country-US`.
+    pub fn is_authorized(&self, client: &Client) -> bool {
+        if self.users.is_empty() {
+            return true;
+        }
+        client.credentials.as_ref().is_some_and(|credentials| {
+            self.users.iter().any(|user| {
+                user.password == credentials.password
+                    && (user.username == credentials.username
+                        || credentials
+                            .username
+                            .strip_prefix(user.username.as_str())
+                            .and_then(|rest| rest.strip_prefix('_'))
+                            .is_some_and(Selector::is_suffix))
+            })
+        })
+    }
+
+    /// Whether `client` sent exactly the credentials of a user, without selectors.
+    pub fn is_user(&self, client: &Client) -> bool {
+        client
+            .credentials
+            .as_ref()
+            .is_some_and(|credentials| self.users.contains(credentials))
+    }
+}
+
+/// Parses a network in CIDR notation, a single ip address is a network of one.
+pub fn parse_net(s: &str) -> Result<IpNet, String> {
+    s.parse::<IpNet>()
+        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("invalid network: {}", s))
+}
+
+/// Parses a `user:pass` pair.
+pub fn parse_user(s: &str) -> Result<Credentials, String> {
+    s.split_once(':')
+        .map(|(username, password)| Credentials::new(username, password))
+        .ok_or_else(|| format!("expected user:pass, got {}", s))
+}
+
+/// Reads `user:pass` lines, empty lines and lines starting with `#` are skipped.
+pub fn load_users(path: &Path) -> io::Result<Vec<Credentials>> {
+    fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| parse_user(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(username: &str, password: &str) -> Client {
+        let mut client = Client::new("127.0.0.1:1".parse().unwrap());
+        client.credentials = Some(Credentials::new(username, password));
+        client
+    }
+
+    #[test]
+    fn usernames_match_in_full_or_with_selectors() {
+        let mut server = Server::new("127.0.0.1", 8080);
+        server.users = vec![
+            Credentials::new("alice", "secret"),
+            Credentials::new("api_user", "pw"),
+        ];
+
+        assert!(server.is_authorized(&client("alice", "secret")));
+        assert!(server.is_authorized(&client("alice_country-US_level-High", "secret")));
+        assert!(server.is_authorized(&client("api_user", "pw")));
+        assert!(server.is_authorized(&client("api_user_country-US", "pw")));
+
+        assert!(!server.is_authorized(&client("alice", "pw")));
+        assert!(!server.is_authorized(&client("alice_bob", "secret")));
+        assert!(!server.is_authorized(&client("alice_level-Unknown", "secret")));
+        assert!(!server.is_authorized(&client("api", "pw")));
+        assert!(!server.is_authorized(&client("api_user_", "pw")));
+
+        assert!(server.is_user(&client("api_user", "pw")));
+        assert!(!server.is_user(&client("alice_country-US", "secret")));
+    }
+}
@@ -0,0 +1,44 @@
+use std::{sync::Arc, time::Duration};
+
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use tokio::{sync::Semaphore, time};
+
+use super::POOL;
+use crate::checker::Checker;
+
+/// Checks the proxies of the pool every `interval`, `concurrency` at a time,
+/// so that dead ones are evicted before a client gets them.
+pub async fn run(interval: Duration, concurrency: usize) {
+    let mut checker = match Checker::new().await {
+        Ok(checker) => checker,
+        Err(err) => {
+            log::error!("Health checks disabled: {}", err);
+            return;
+        }
+    };
+    checker.max_tries = 1;
+
+    let sem = Arc::new(Semaphore::new(concurrency.max(1)));
+    loop {
+        time::sleep(interval).await;
+
+        let proxies = POOL.lock().snapshot();
+        log::debug!("Health checking {} proxies", proxies.len());
+        let mut futures = FuturesUnordered::new();
+        for proxy in proxies {
+            let permit = Arc::clone(&sem).acquire_owned().await;
+            let mut checker = checker.clone();
+            futures.push(tokio::spawn(async move {
+                let _ = permit;
+                let mut checked = proxy.to_proxy();
+                checker.expected_types = checked.expected_types.clone();
+                checker.check_proxy(&mut checked).await;
+                // proxies that could not be checked, e.g. for lack of judges, leave no logs
+                if !checked.logs.is_empty() {
+                    POOL.lock().record_check(&proxy, &checked);
+                }
+            }));
+        }
+        while futures.next().await.is_some() {}
+    }
+}
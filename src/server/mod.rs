@@ -1,8 +1,29 @@
+pub mod admin;
+pub mod client;
+pub mod health;
 pub mod proxy_pool;
+pub mod selector;
+pub mod session;
+pub mod socks5;
+pub mod strategy;
 
-use std::time::Duration;
+use std::{
+    collections::HashSet,
+    future::Future,
+    io::{self, Error, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use hyper::{server::conn::Http, service::service_fn, Body, Method, Request, Response, StatusCode};
+use hyper::{
+    ext::ReasonPhrase,
+    header::{HeaderValue, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION},
+    server::conn::Http,
+    service::service_fn,
+    Body, Method, Request, Response, StatusCode,
+};
+use ipnet::IpNet;
 use lazy_static::lazy_static;
 use parking_lot::Mutex;
 use tokio::{
@@ -11,139 +32,627 @@ use tokio::{
     time::timeout,
 };
 
-use self::proxy_pool::{ProxyPool, SimpleProxy, LIVE_PROXIES};
-use crate::utils::http::response::ResponseParser;
+use self::{
+    client::Client,
+    proxy_pool::{ProxyPool, SimpleProxy, LIVE_PROXIES},
+    selector::{Selector, HEADER_PREFIX},
+    session::{session_id, SessionKey, Sessions},
+    strategy::Strategy,
+};
+use crate::{
+    error::ProxyError,
+    metrics::{self, SERVER_REQUESTS, UPSTREAM_LATENCY},
+    negotiators::{self, get_negotiator, TargetAddr},
+    proxy::Credentials,
+    resolver::Resolver,
+    types::ProxyProtocol,
+    utils::{host_port, http::response::ResponseParser},
+};
 
 lazy_static! {
     static ref POOL: Mutex<ProxyPool> = Mutex::new(ProxyPool::new());
+    static ref SESSIONS: Mutex<Sessions> = Mutex::new(Sessions::default());
 }
 
 const TIMEOUT_IN_SECONDS: u64 = 8;
 
-#[derive(Debug)]
+/// Local proxy server relaying clients through upstreams from a pool.
+///
+/// The pool is filled from the global [`LIVE_PROXIES`] queue, which an
+/// embedding caller must feed with checked proxies, e.g. from a
+/// [`Finder`](crate::Finder) stream:
+///
+/// ```no_run
+/// use futures_util::StreamExt;
+/// use proxy_rs::{server::proxy_pool::LIVE_PROXIES, Finder, Server};
+///
+/// # async fn run() -> Result<(), proxy_rs::ProxyError> {
+/// let mut proxies = Finder::builder().build().stream().await?;
+/// tokio::spawn(async move {
+///     while let Some(proxy) = proxies.next().await {
+///         let _ = LIVE_PROXIES.push_wait(proxy).await;
+///     }
+/// });
+/// Server::new("127.0.0.1", 8080).start().await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
 pub struct Server {
+    /// Host the proxy listeners bind to.
     pub host: String,
+    /// Port of the listener speaking HTTP and SOCKS5.
     pub port: u16,
+    /// Port of an additional listener that only speaks SOCKS5.
+    pub socks_port: Option<u16>,
+    /// Port of the admin API, see [`admin`].
+    pub admin_port: Option<u16>,
+    /// Host of the admin API, loopback by default so that the pool can not be
+    /// changed from wherever the proxy is reachable.
+    pub admin_host: String,
+    /// The maximum number of upstream proxies tried for a single request.
+    pub max_attempts: usize,
+    /// How upstreams are picked from the pool.
+    pub strategy: Strategy,
+    /// Keys identifying sticky sessions, in order of preference. Empty disables them.
+    pub session_keys: Vec<SessionKey>,
+    /// Seconds a session keeps its upstream after its last request.
+    pub session_ttl: u64,
+    /// Users allowed to use the server. Empty allows anyone.
+    pub users: Vec<Credentials>,
+    /// Networks allowed to use the server. Empty allows any network.
+    pub allow: Vec<IpNet>,
+    /// Networks denied from using the server, even when allowed.
+    pub deny: Vec<IpNet>,
+    /// Seconds between health checks of the pool, `0` disables them.
+    pub health_interval: u64,
+    /// The maximum number of proxies health checked at once.
+    pub health_concurrency: usize,
 }
 
 impl Server {
+    /// Server on `host:port` without SOCKS5-only or admin listeners, open to
+    /// anyone, with up to 3 attempts per request and health checks every
+    /// minute.
     pub fn new(host: &str, port: u16) -> Self {
         Self {
             host: host.to_string(),
             port,
+            socks_port: None,
+            admin_port: None,
+            admin_host: "127.0.0.1".to_string(),
+            max_attempts: 3,
+            strategy: Strategy::default(),
+            session_keys: vec![],
+            session_ttl: 600,
+            users: vec![],
+            allow: vec![],
+            deny: vec![],
+            health_interval: 60,
+            health_concurrency: 10,
         }
     }
 
+    /// Waits for a first proxy in [`LIVE_PROXIES`], then serves forever.
+    /// Listeners that fail to bind are skipped.
     pub async fn start(&self) {
-        while LIVE_PROXIES.is_empty() {
-            continue;
+        LIVE_PROXIES.wait_for_item().await;
+
+        POOL.lock().strategy = self.strategy.build();
+        SESSIONS.lock().ttl = Duration::from_secs(self.session_ttl);
+        let server = Arc::new(self.clone());
+        if self.health_interval > 0 {
+            tokio::task::spawn(health::run(
+                Duration::from_secs(self.health_interval),
+                self.health_concurrency,
+            ));
+        }
+        if let Some(socks_port) = self.socks_port {
+            let addr = host_port(&self.host, socks_port);
+            if let Ok(listener) = TcpListener::bind(&addr).await {
+                log::info!("Listening on socks5://{}", addr);
+                tokio::task::spawn(accept_loop(Arc::clone(&server), listener, true));
+            }
+        }
+        if let Some(admin_port) = self.admin_port {
+            let addr = host_port(&self.admin_host, admin_port);
+            if let Ok(listener) = TcpListener::bind(&addr).await {
+                log::info!("Admin API listening on http://{}", addr);
+                tokio::task::spawn(admin::accept_loop(Arc::clone(&server), listener));
+            }
         }
 
-        let addr = format!("{}:{}", self.host, self.port);
+        let addr = host_port(&self.host, self.port);
         if let Ok(listener) = TcpListener::bind(&addr).await {
             log::info!("Listening on http://{}", addr);
+            accept_loop(server, listener, false).await;
+        }
+    }
+}
 
-            loop {
-                if let Ok((stream, addr)) = listener.accept().await {
-                    log::info!("Accepted connection from {}", addr);
-                    tokio::task::spawn(async move {
-                        if let Err(err) = Http::new()
-                            .http1_title_case_headers(true)
-                            .http1_title_case_headers(true)
-                            .serve_connection(stream, service_fn(handle_stream))
-                            .with_upgrades()
-                            .await
-                        {
-                            log::error!("Connection error: {}", err);
-                        }
-                    });
-                }
+/// Accepts clients forever. SOCKS5 clients are recognized by their first byte
+/// unless `socks_only` is set. Clients from denied networks are dropped before
+/// any handshake, so they can not try credentials.
+async fn accept_loop(server: Arc<Server>, listener: TcpListener, socks_only: bool) {
+    loop {
+        if let Ok((stream, addr)) = listener.accept().await {
+            if !server.is_allowed(&Client::new(addr)) {
+                log::warn!("Rejected client {}", addr);
+                continue;
             }
+            log::info!("Accepted connection from {}", addr);
+            let server = Arc::clone(&server);
+            tokio::task::spawn(async move {
+                let mut version = [0u8; 1];
+                if socks_only
+                    || matches!(stream.peek(&mut version).await, Ok(1) if version[0] == 0x05)
+                {
+                    if let Err(err) = socks5::handle_socks5_stream(server, stream, addr).await {
+                        log::error!("Connection error: {}", err);
+                    }
+                } else if let Err(err) = Http::new()
+                    .http1_title_case_headers(true)
+                    .http1_title_case_headers(true)
+                    .serve_connection(
+                        stream,
+                        service_fn(|request| handle_stream(Arc::clone(&server), addr, request)),
+                    )
+                    .with_upgrades()
+                    .await
+                {
+                    log::error!("Connection error: {}", err);
+                }
+            });
         }
     }
 }
 
-async fn handle_stream(request: Request<Body>) -> Result<Response<Body>, hyper::Error> {
-    if let Some(mut proxy) = get_proxy(request.method()) {
-        log::info!("Proxying to: {}", proxy.as_text());
+async fn handle_stream(
+    server: Arc<Server>,
+    addr: SocketAddr,
+    request: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    let protocol = if request.method() == Method::CONNECT {
+        "connect"
+    } else {
+        "http"
+    };
+    let response = serve_request(server, addr, request).await;
+    if let Ok(response) = &response {
+        SERVER_REQUESTS
+            .with_label_values(&[protocol, response.status().as_str()])
+            .inc();
+    }
+    response
+}
 
-        if request.method() == Method::CONNECT {
-            tokio::task::spawn(async move {
-                if let Err(err) = handle_connect_stream(request, proxy).await {
-                    log::error!("Failed to connect proxy: {}", err);
+async fn serve_request(
+    server: Arc<Server>,
+    addr: SocketAddr,
+    mut request: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    let client = Client::from_request(addr, request.headers());
+    if !server.is_authorized(&client) {
+        log::warn!("Unauthorized client {}", addr);
+        return Ok(Response::builder()
+            .status(StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+            .header(PROXY_AUTHENTICATE, "Basic realm=\"proxy-rs\"")
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    let session = session_id(&server.session_keys, &client);
+    let selector = match Selector::from_request(&client, Some(request.headers())) {
+        Ok(selector) => selector,
+        Err(err) => {
+            log::warn!("Invalid selector from {}: {}", addr, err);
+            return Ok(bad_request());
+        }
+    };
+    if request.method() == Method::CONNECT {
+        let target = match request.uri().authority() {
+            Some(authority) => {
+                TargetAddr::new(authority.host(), authority.port_u16().unwrap_or(443))
+            }
+            None => return Ok(bad_request()),
+        };
+        let target = &target;
+        match connect_upstream(
+            tunnel_scheme(target),
+            server.max_attempts,
+            session.as_deref(),
+            &selector,
+            |proxy| async move { open_tunnel(&proxy, target).await },
+        )
+        .await
+        {
+            Ok((proxy, (proxy_stream, status))) => {
+                tokio::task::spawn(async move {
+                    if let Err(err) = handle_connect_stream(request, proxy, proxy_stream).await {
+                        log::error!("Failed to connect proxy: {}", err);
+                    }
+                });
+                Ok(status.into_response())
+            }
+            Err(err) => {
+                log::error!("Failed to connect proxy: {}", err);
+                // relay the upstream's answer when it rejected the tunnel
+                match err
+                    .into_inner()
+                    .and_then(|err| err.downcast::<StatusLine>().ok())
+                {
+                    Some(status) => Ok(status.into_response()),
+                    None => Ok(bad_gateway()),
                 }
-            });
-            Ok(Response::new(Body::empty()))
-        } else {
-            let proxy_stream = TcpStream::connect(proxy.as_text()).await.unwrap();
-            if let Ok((mut sender, conn)) = hyper::client::conn::Builder::new()
-                .http1_title_case_headers(true)
-                .http1_preserve_header_case(true)
-                .handshake(proxy_stream)
-                .await
-            {
+            }
+        }
+    } else {
+        let uri = request.uri().clone();
+        let target = match uri.host() {
+            Some(host) => TargetAddr::new(host, uri.port_u16().unwrap_or(80)),
+            None => return Ok(bad_request()),
+        };
+        let target = &target;
+        let (proxy, proxy_stream) = match connect_upstream(
+            ProxyProtocol::Http,
+            server.max_attempts,
+            session.as_deref(),
+            &selector,
+            |proxy| async move { open_http_stream(&proxy, target).await },
+        )
+        .await
+        {
+            Ok(upstream) => upstream,
+            Err(err) => {
+                log::error!("Failed to connect proxy: {}", err);
+                return Ok(bad_gateway());
+            }
+        };
+        prepare_request(&proxy, &mut request);
+
+        match hyper::client::conn::Builder::new()
+            .http1_title_case_headers(true)
+            .http1_preserve_header_case(true)
+            .handshake(proxy_stream)
+            .await
+        {
+            Ok((mut sender, conn)) => {
                 tokio::task::spawn(async move {
                     if let Err(err) = conn.await {
                         log::error!("Failed to connect proxy: {}", err);
                     }
                 });
                 let response = sender.send_request(request).await;
-                proxy.request_stat += 1;
-                POOL.lock().put(proxy);
+                let error = response
+                    .as_ref()
+                    .err()
+                    .map(|_| ProxyError::RequestFailed.kind());
+                POOL.lock().release(&proxy, error);
                 response
-            } else {
-                Ok(Response::builder()
-                    .status(StatusCode::BAD_GATEWAY)
-                    .body(Body::empty())
-                    .unwrap())
+            }
+            Err(err) => {
+                log::error!("Failed to connect proxy: {}", err);
+                POOL.lock().release(&proxy, Some(error_kind(&err)));
+                Ok(bad_gateway())
+            }
+        }
+    }
+}
+
+fn bad_request() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn bad_gateway() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Picks upstreams serving `scheme` until `connect` succeeds on one, trying
+/// at most `max_attempts` of them. Failures are counted against the proxy.
+async fn connect_upstream<T, F, Fut>(
+    scheme: ProxyProtocol,
+    max_attempts: usize,
+    session: Option<&str>,
+    selector: &Selector,
+    mut connect: F,
+) -> io::Result<(SimpleProxy, T)>
+where
+    F: FnMut(SimpleProxy) -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    let mut last_error = Error::new(ErrorKind::NotFound, "no proxy available");
+    // a failed upstream may still rank first, the next attempts skip it
+    let mut tried = HashSet::new();
+    for attempt in 1..=max_attempts.max(1) {
+        let proxy = match take_proxy(scheme, session, selector, &tried).await {
+            Some(proxy) => proxy,
+            None => break,
+        };
+        log::info!("Proxying to: {} (attempt {})", proxy.as_text(), attempt);
+
+        let stime = Instant::now();
+        let result = connect(proxy.clone()).await;
+        UPSTREAM_LATENCY
+            .with_label_values(&[if result.is_ok() { "ok" } else { "error" }])
+            .observe(stime.elapsed().as_secs_f64());
+        match result {
+            Ok(upstream) => {
+                if let Some(session) = session {
+                    SESSIONS.lock().pin(session, &proxy);
+                }
+                return Ok((proxy, upstream));
+            }
+            Err(err) => {
+                log::warn!("{} failed: {}", proxy.as_text(), err);
+                POOL.lock().release(&proxy, Some(error_kind(&err)));
+                if let Some(session) = session {
+                    SESSIONS.lock().unpin(session);
+                }
+                tried.insert(host_port(&proxy.host, proxy.port));
+                last_error = err;
             }
         }
-    } else {
-        Ok(Response::builder()
-            .status(StatusCode::BAD_GATEWAY)
-            .body(Body::empty())
-            .unwrap())
+    }
+    Err(last_error)
+}
+
+/// Key a failed upstream is counted under in its `error_stat`, the same as
+/// [`ProxyError::kind`] of the checks.
+fn error_kind(err: &(dyn std::error::Error + 'static)) -> &'static str {
+    if let Some(err) = err.downcast_ref::<ProxyError>() {
+        return err.kind();
+    }
+    let err = match err.downcast_ref::<io::Error>() {
+        Some(err) => err,
+        None => return ProxyError::connect(ErrorKind::Other.into()).kind(),
+    };
+    // negotiators fail with a `ProxyError` wrapped in an `io::Error`, refused
+    // tunnels with their `StatusLine`
+    let inner = err.get_ref();
+    if let Some(inner) = inner.and_then(|inner| inner.downcast_ref::<ProxyError>()) {
+        return inner.kind();
+    }
+    if let Some(status) = inner.and_then(|inner| inner.downcast_ref::<StatusLine>()) {
+        return status.error().kind();
+    }
+    ProxyError::connect(err.kind().into()).kind()
+}
+
+/// Takes the upstream pinned to `session` while it is alive and matches
+/// `selector`, or a new one that was not `tried` yet.
+async fn take_proxy(
+    scheme: ProxyProtocol,
+    session: Option<&str>,
+    selector: &Selector,
+    tried: &HashSet<String>,
+) -> Option<SimpleProxy> {
+    let pinned = session.and_then(|session| SESSIONS.lock().get(session));
+    if let Some((host, port)) = pinned {
+        let proxy = POOL.lock().get_by_addr(&host, port, scheme, selector);
+        if proxy.is_some() {
+            return proxy;
+        }
+    }
+    loop {
+        {
+            let mut pool = POOL.lock();
+            let proxy = pool.get(scheme, selector, tried);
+            // only wait for new proxies when there is nothing else to serve from,
+            // filtered requests do not wait for a matching proxy to be found
+            if proxy.is_some() || !selector.is_empty() || !pool.is_empty() {
+                return proxy;
+            }
+        }
+        LIVE_PROXIES.wait_for_item().await;
+    }
+}
+
+/// Opens the stream a plain HTTP request to `target` is sent over.
+async fn open_http_stream(proxy: &SimpleProxy, target: &TargetAddr) -> io::Result<TcpStream> {
+    if proxy.types.iter().any(|(p, _)| *p == ProxyProtocol::Http) {
+        let timeout_in = Duration::from_secs(TIMEOUT_IN_SECONDS);
+        return timeout(timeout_in, TcpStream::connect(proxy.as_text())).await?;
+    }
+    let (stream, _) = open_tunnel(proxy, target).await?;
+    Ok(stream)
+}
+
+/// Rewrites `request` to suit the protocol `proxy` is reached with.
+fn prepare_request(proxy: &SimpleProxy, request: &mut Request<Body>) {
+    // these are meant for this server, not for the upstream
+    let headers = request.headers_mut();
+    let own_headers: Vec<_> = headers
+        .keys()
+        .filter(|name| name.as_str().starts_with(HEADER_PREFIX))
+        .cloned()
+        .collect();
+    for name in own_headers {
+        headers.remove(name);
+    }
+    headers.remove(PROXY_AUTHORIZATION);
+
+    if proxy.types.iter().any(|(p, _)| *p == ProxyProtocol::Http) {
+        if let Some(credentials) = &proxy.credentials {
+            if let Ok(value) = HeaderValue::from_str(&credentials.basic_auth()) {
+                request.headers_mut().insert(PROXY_AUTHORIZATION, value);
+            }
+        }
+    } else if let Some(path) = request.uri().path_and_query() {
+        // tunneled requests reach the origin server, which expects the path only
+        if let Ok(path) = path.as_str().parse() {
+            *request.uri_mut() = path;
+        }
     }
 }
 
 async fn handle_connect_stream(
     request: Request<Body>,
-    mut proxy: SimpleProxy,
+    proxy: SimpleProxy,
+    mut proxy_stream: TcpStream,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let uri = request.uri().clone();
-    if let Some(host) = uri.host() {
-        if let Ok(mut upgrade) = hyper::upgrade::on(request).await {
-            let mut proxy_stream = TcpStream::connect(proxy.as_text()).await?;
-            let connect_status =
-                send_connect_request(&mut proxy_stream, host, TIMEOUT_IN_SECONDS).await;
-
-            if connect_status {
-                tokio::io::copy_bidirectional(&mut upgrade, &mut proxy_stream).await?;
-                proxy.request_stat += 1;
-                POOL.lock().put(proxy);
-            }
+    let result: Result<_, Box<dyn std::error::Error>> = match hyper::upgrade::on(request).await {
+        Ok(mut upgrade) => tokio::io::copy_bidirectional(&mut upgrade, &mut proxy_stream)
+            .await
+            .map(|(sent, received)| metrics::record_tunnel(sent, received))
+            .map_err(|err| err.into()),
+        Err(err) => Err(err.into()),
+    };
+    let error = result.as_ref().err().map(|err| error_kind(&**err));
+    POOL.lock().release(&proxy, error);
+    result.map(|_| ())
+}
+
+/// Status line an upstream answered a tunnel request with.
+#[derive(Debug)]
+struct StatusLine {
+    code: StatusCode,
+    reason: Option<String>,
+}
+
+impl StatusLine {
+    /// Status of a tunnel opened by a SOCKS handshake.
+    fn established() -> Self {
+        Self {
+            code: StatusCode::OK,
+            reason: Some("Connection established".to_string()),
+        }
+    }
+
+    /// Error a refused tunnel is counted as, like the CONNECT negotiators do.
+    fn error(&self) -> ProxyError {
+        match self.code {
+            StatusCode::PROXY_AUTHENTICATION_REQUIRED => ProxyError::AuthRequired,
+            code => ProxyError::BadStatus(code.as_u16()),
         }
     }
-    Ok(())
+
+    fn into_response(self) -> Response<Body> {
+        let mut response = Response::builder()
+            .status(self.code)
+            .body(Body::empty())
+            .unwrap();
+        if let Some(reason) = self
+            .reason
+            .and_then(|reason| ReasonPhrase::try_from(reason).ok())
+        {
+            response.extensions_mut().insert(reason);
+        }
+        response
+    }
 }
 
-fn get_proxy(method: &Method) -> Option<SimpleProxy> {
-    let mut pool = POOL.lock();
-    if method == Method::CONNECT {
-        pool.get("HTTPS")
+impl std::fmt::Display for StatusLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.reason {
+            Some(reason) => write!(f, "{} {}", self.code.as_u16(), reason),
+            None => write!(f, "{}", self.code),
+        }
+    }
+}
+
+impl std::error::Error for StatusLine {}
+
+/// Scheme an upstream must serve to open a tunnel to `target`, CONNECT:80 and
+/// CONNECT:25 proxies only tunnel to their own port.
+fn tunnel_scheme(target: &TargetAddr) -> ProxyProtocol {
+    match target.port() {
+        80 => ProxyProtocol::Connect80,
+        25 => ProxyProtocol::Connect25,
+        _ => ProxyProtocol::Https,
+    }
+}
+
+/// Opens a tunnel to `target` through `proxy`, using the first tunneling
+/// protocol the proxy was found to support.
+async fn open_tunnel(
+    proxy: &SimpleProxy,
+    target: &TargetAddr,
+) -> io::Result<(TcpStream, StatusLine)> {
+    let timeout_in = Duration::from_secs(TIMEOUT_IN_SECONDS);
+    let mut stream = timeout(timeout_in, TcpStream::connect(proxy.as_text())).await??;
+    let supports = |proto: ProxyProtocol| proxy.types.iter().any(|(p, _)| *p == proto);
+
+    if supports(ProxyProtocol::Https)
+        || (supports(ProxyProtocol::Connect80) && target.port() == 80)
+        || (supports(ProxyProtocol::Connect25) && target.port() == 25)
+    {
+        let authority = target.to_string();
+        let status = send_connect_request(
+            &mut stream,
+            &authority,
+            proxy.credentials.as_ref(),
+            TIMEOUT_IN_SECONDS,
+        )
+        .await
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid CONNECT response"))?;
+        if !status.code.is_success() {
+            return Err(Error::new(ErrorKind::ConnectionRefused, status));
+        }
+        return Ok((stream, status));
+    } else if supports(ProxyProtocol::Socks5) {
+        timeout(
+            timeout_in,
+            negotiators::socks5::handshake(&mut stream, target, proxy.credentials.as_ref()),
+        )
+        .await??;
+    } else if supports(ProxyProtocol::Socks4a) {
+        timeout(
+            timeout_in,
+            negotiators::socks4::handshake(&mut stream, target, true),
+        )
+        .await??;
+    } else if supports(ProxyProtocol::Socks4) {
+        let target = match target {
+            TargetAddr::Domain(host, port) => Resolver::new()
+                .resolve_all(host)
+                .await
+                .into_iter()
+                .find(IpAddr::is_ipv4)
+                .map(|ip| TargetAddr::Ip(SocketAddr::new(ip, *port)))
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, "failed to resolve target"))?,
+            target => target.clone(),
+        };
+        timeout(
+            timeout_in,
+            negotiators::socks4::handshake(&mut stream, &target, false),
+        )
+        .await??;
+    } else if let Some(negotiator) = proxy.types.iter().find_map(|(proto, _)| match proto {
+        ProxyProtocol::Custom(_) => get_negotiator(proto),
+        _ => None,
+    }) {
+        timeout(
+            timeout_in,
+            negotiator.tunnel(&mut stream, target, proxy.credentials.as_ref()),
+        )
+        .await??;
     } else {
-        pool.get("HTTP")
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "proxy does not support tunneling",
+        ));
     }
+    Ok((stream, StatusLine::established()))
 }
 
 async fn send_connect_request<R: AsyncRead + AsyncWrite + Unpin>(
     stream: &mut R,
-    host: &str,
+    authority: &str,
+    credentials: Option<&Credentials>,
     timeout_in_seconds: u64,
-) -> bool {
+) -> Option<StatusLine> {
+    let auth = credentials
+        .map(|credentials| format!("Proxy-Authorization: {}\r\n", credentials.basic_auth()))
+        .unwrap_or_default();
     let connect = format!(
-        "CONNECT {0}:443 HTTP/1.1\r\nHost: {0}:443\r\nProxy-Connection: Keep-Alive\r\n\r\n",
-        host
+        "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n{1}Proxy-Connection: Keep-Alive\r\n\r\n",
+        authority, auth
     );
     // Send data
     if let Ok(Ok(_)) = timeout(
@@ -156,17 +665,25 @@ async fn send_connect_request<R: AsyncRead + AsyncWrite + Unpin>(
         let data = read_timeout(stream, timeout_in_seconds).await;
         let response = ResponseParser::parse(data.as_slice());
 
-        if let Some(status_code) = response.status_code {
-            return status_code == 200;
+        if let Some(code) = response
+            .status_code
+            .and_then(|code| StatusCode::from_u16(code).ok())
+        {
+            return Some(StatusLine {
+                code,
+                reason: response.reason,
+            });
         }
     }
-    false
+    None
 }
 
+/// Reads a response head, stopping at the blank line so that tunneled data is
+/// left in the stream.
 async fn read_timeout<R: AsyncRead + Unpin>(reader: &mut R, timeout_in_seconds: u64) -> Vec<u8> {
     let mut data = vec![];
-    loop {
-        let mut buf = [0; 512];
+    while !data.ends_with(b"\r\n\r\n") {
+        let mut buf = [0; 1];
         if let Ok(Ok(buf_size)) = timeout(
             Duration::from_secs(timeout_in_seconds),
             reader.read(&mut buf),
@@ -183,3 +700,86 @@ async fn read_timeout<R: AsyncRead + Unpin>(reader: &mut R, timeout_in_seconds:
     }
     data
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_keys_match_the_checks() {
+        let refused = Error::from(ErrorKind::ConnectionRefused);
+        assert_eq!(error_kind(&refused), ProxyError::ConnectRefused.kind());
+        let timeout = Error::from(ErrorKind::TimedOut);
+        assert_eq!(error_kind(&timeout), ProxyError::ConnectTimeout.kind());
+        let reset = Error::from(ErrorKind::ConnectionReset);
+        assert_eq!(error_kind(&reset), "connection_error");
+
+        let negotiated = Error::from(ProxyError::BadStatus(403));
+        assert_eq!(error_kind(&negotiated), ProxyError::BadStatus(403).kind());
+
+        let refused = |code| {
+            let status = StatusLine { code, reason: None };
+            Error::new(ErrorKind::ConnectionRefused, status)
+        };
+        assert_eq!(
+            error_kind(&refused(StatusCode::FORBIDDEN)),
+            ProxyError::BadStatus(403).kind()
+        );
+        assert_eq!(
+            error_kind(&refused(StatusCode::PROXY_AUTHENTICATION_REQUIRED)),
+            ProxyError::AuthRequired.kind()
+        );
+    }
+
+    #[test]
+    fn tunnels_to_ports_80_and_25_use_connect_proxies() {
+        let mut connect80 = SimpleProxy::new("127.0.0.1", 8000, vec![ProxyProtocol::Connect80]);
+        let scheme = tunnel_scheme(&TargetAddr::new("example.com", 80));
+        assert!(connect80.get_schemes().contains(&scheme));
+        let scheme = tunnel_scheme(&TargetAddr::new("example.com", 443));
+        assert!(!connect80.get_schemes().contains(&scheme));
+
+        let mut https = SimpleProxy::new("127.0.0.1", 8001, vec![ProxyProtocol::Https]);
+        let scheme = tunnel_scheme(&TargetAddr::new("mail.example.com", 25));
+        assert!(https.get_schemes().contains(&scheme));
+    }
+
+    #[tokio::test]
+    async fn retries_skip_a_failed_upstream() {
+        let proxy = |port, requests, errors| {
+            let mut proxy = SimpleProxy::new("127.0.0.1", port, vec![ProxyProtocol::Http]);
+            proxy.request_stat = requests;
+            proxy
+                .error_stat
+                .insert("connect_refused".to_string(), errors);
+            proxy
+        };
+        {
+            let mut pool = POOL.lock();
+            pool.strategy = Strategy::Best.build();
+            // 1 error in 11 requests ranks before 2 in 10
+            pool.put(proxy(8001, 11, 1));
+            pool.put(proxy(8002, 10, 2));
+        }
+
+        let mut attempts = vec![];
+        let result = connect_upstream(
+            ProxyProtocol::Http,
+            3,
+            None,
+            &Selector::default(),
+            |proxy: SimpleProxy| {
+                attempts.push(proxy.port);
+                async move {
+                    match proxy.port {
+                        8001 => Err(Error::from(ErrorKind::ConnectionRefused)),
+                        _ => Ok(()),
+                    }
+                }
+            },
+        )
+        .await;
+        assert_eq!(result.unwrap().0.port, 8002);
+        assert_eq!(attempts, vec![8001, 8002]);
+    }
+}
@@ -1,30 +1,64 @@
-use crate::{proxy::Proxy, resolver::GeoData};
-use concurrent_queue::ConcurrentQueue;
+use super::{
+    selector::Selector,
+    strategy::{SelectionStrategy, Strategy},
+};
+use crate::{
+    metrics::POOL_SIZE,
+    proxy::{Credentials, Proxy},
+    resolver::GeoData,
+    types::{AnonymityLevel, ProxyProtocol},
+    utils::{host_port, queue::Queue},
+};
 use lazy_static::lazy_static;
 use std::{
     cmp::Ordering,
-    collections::{BTreeMap, BinaryHeap, VecDeque},
+    collections::{BTreeMap, HashSet, VecDeque},
+    time::Instant,
 };
 
 lazy_static! {
-    pub static ref LIVE_PROXIES: ConcurrentQueue<Proxy> = ConcurrentQueue::bounded(20);
+    pub static ref LIVE_PROXIES: Queue<Proxy> = Queue::bounded(20);
 }
 
 #[derive(Debug, Clone)]
 pub struct SimpleProxy {
     pub host: String,
     pub port: u16,
+    pub hostname: Option<String>,
+    pub credentials: Option<Credentials>,
     pub geo: GeoData,
-    pub types: Vec<(String, Option<String>)>,
-    pub schemes: Vec<String>,
+    pub types: Vec<(ProxyProtocol, Option<AnonymityLevel>)>,
+    pub schemes: Vec<ProxyProtocol>,
 
     pub runtimes: Vec<f64>,
     pub request_stat: i32,
     pub error_stat: BTreeMap<String, i32>,
+
+    /// Requests currently going through this proxy.
+    pub connections: usize,
+    pub last_used: Option<Instant>,
 }
 impl SimpleProxy {
+    /// A proxy added by hand, assumed to support `types`.
+    pub fn new(host: &str, port: u16, types: Vec<ProxyProtocol>) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            hostname: None,
+            credentials: None,
+            geo: GeoData::default(),
+            types: types.into_iter().map(|proto| (proto, None)).collect(),
+            schemes: vec![],
+            runtimes: vec![],
+            request_stat: 0,
+            error_stat: BTreeMap::new(),
+            connections: 0,
+            last_used: None,
+        }
+    }
+
     pub fn as_text(&self) -> String {
-        format!("{}:{}", self.host, self.port)
+        host_port(self.hostname.as_ref().unwrap_or(&self.host), self.port)
     }
 
     pub fn error_rate(&self) -> f64 {
@@ -43,23 +77,69 @@ impl SimpleProxy {
         sum / self.runtimes.len() as f64
     }
 
-    pub fn get_schemes(&mut self) -> Vec<String> {
+    /// Marks the proxy as used by one more request and returns a copy of it.
+    fn take(&mut self) -> SimpleProxy {
+        self.connections += 1;
+        self.last_used = Some(Instant::now());
+        self.clone()
+    }
+
+    pub fn get_schemes(&mut self) -> Vec<ProxyProtocol> {
         if self.schemes.is_empty() {
             for (proxy_type, _) in &self.types {
-                if !self.schemes.contains(&"HTTP".to_string())
-                    && ["HTTP", "CONNECT:80", "SOCKS4", "SOCKS5"].contains(&proxy_type.as_str())
-                {
-                    self.schemes.push("HTTP".to_string());
-                }
-                if !self.schemes.contains(&"HTTPS".to_string())
-                    && ["HTTPS", "SOCKS4", "SOCKS5"].contains(&proxy_type.as_str())
-                {
-                    self.schemes.push("HTTPS".to_string());
+                for scheme in proxy_type.schemes() {
+                    if !self.schemes.contains(&scheme) {
+                        self.schemes.push(scheme);
+                    }
                 }
             }
         }
         self.schemes.clone()
     }
+
+    /// A [`Proxy`] to check the protocols this proxy was found with.
+    pub fn to_proxy(&self) -> Proxy {
+        Proxy {
+            host: self.host.clone(),
+            port: self.port,
+            hostname: self.hostname.clone(),
+            credentials: self.credentials.clone(),
+            provider: None,
+            expected_types: self.types.iter().map(|(proto, _)| *proto).collect(),
+            geo: self.geo.clone(),
+            types: vec![],
+            schemes: vec![],
+            logs: vec![],
+            negotiator_proto: ProxyProtocol::Http,
+            verify_ssl: false,
+            timeout: 5,
+            runtimes: vec![],
+            tcp_stream: None,
+            tls_stream: None,
+            request_stat: 0,
+            error_stat: BTreeMap::new(),
+            is_working: false,
+        }
+    }
+}
+
+impl From<&Proxy> for SimpleProxy {
+    fn from(proxy: &Proxy) -> Self {
+        Self {
+            host: proxy.host.clone(),
+            port: proxy.port,
+            hostname: proxy.hostname.clone(),
+            credentials: proxy.credentials.clone(),
+            geo: proxy.geo.clone(),
+            types: proxy.types.clone(),
+            schemes: proxy.schemes.clone(),
+            runtimes: proxy.runtimes.clone(),
+            request_stat: proxy.request_stat,
+            error_stat: proxy.error_stat.clone(),
+            connections: 0,
+            last_used: None,
+        }
+    }
 }
 
 impl Ord for SimpleProxy {
@@ -92,10 +172,12 @@ impl PartialEq for SimpleProxy {
 
 #[derive(Debug)]
 pub struct ProxyPool {
-    pool: BinaryHeap<SimpleProxy>,
+    pool: Vec<SimpleProxy>,
     newcomers: VecDeque<SimpleProxy>,
+    /// `host:port` of proxies that are never taken into the pool again.
+    banned: HashSet<String>,
 
-    pub strategy: String,
+    pub strategy: Box<dyn SelectionStrategy>,
     pub min_req_proxy: i32,
     pub max_error_rate: f64,
     pub max_avg_resp_time: f64,
@@ -105,9 +187,10 @@ pub struct ProxyPool {
 impl ProxyPool {
     pub fn new() -> Self {
         Self {
-            pool: BinaryHeap::new(),
+            pool: vec![],
             newcomers: VecDeque::new(),
-            strategy: "best".to_string(),
+            banned: HashSet::new(),
+            strategy: Strategy::Best.build(),
             min_req_proxy: 5,
             max_error_rate: 0.5,
             max_avg_resp_time: 8.0,
@@ -115,94 +198,228 @@ impl ProxyPool {
         }
     }
 
-    pub fn get(&mut self, schemes: &str) -> Option<SimpleProxy> {
-        let scheme = schemes.to_uppercase();
+    /// Takes a proxy serving `scheme`. The proxy stays in the pool and must be
+    /// handed back with [`ProxyPool::release`] once the request is done.
+    pub fn get(
+        &mut self,
+        scheme: ProxyProtocol,
+        selector: &Selector,
+        tried: &HashSet<String>,
+    ) -> Option<SimpleProxy> {
         if self.pool.len() + self.newcomers.len() < self.min_queue as usize {
-            return self.import(&scheme);
-        } else if !self.newcomers.is_empty() {
-            return self.newcomers.pop_front();
-        } else if self.strategy == "best" {
-            let mut cache = VecDeque::new();
-            while !self.pool.is_empty() {
-                if let Some(mut proxy) = self.pool.pop() {
-                    if proxy.get_schemes().contains(&scheme) {
-                        self.pool.extend(cache);
-                        return Some(proxy);
-                    } else {
-                        cache.push_back(proxy)
-                    }
-                } else {
-                    break;
-                }
+            if let Some(proxy) = self.import(scheme, selector, tried) {
+                return Some(proxy);
             }
-            self.pool.extend(cache);
-            return self.import(&scheme);
         }
-        None
-    }
+        let usable = |proxy: &SimpleProxy| {
+            proxy.schemes.contains(&scheme)
+                && selector.matches(proxy)
+                && !tried.contains(&host_port(&proxy.host, proxy.port))
+        };
 
-    pub fn import(&mut self, expected_schemes: &String) -> Option<SimpleProxy> {
-        loop {
-            if let Ok(proxy) = LIVE_PROXIES.pop() {
-                let mut proxy = SimpleProxy {
-                    host: proxy.host.clone(),
-                    port: proxy.port,
-                    geo: proxy.geo.clone(),
-                    types: proxy.types.clone(),
-                    schemes: proxy.schemes.clone(),
-                    runtimes: proxy.runtimes.clone(),
-                    request_stat: proxy.request_stat,
-                    error_stat: proxy.error_stat.clone(),
-                };
-                if !proxy.get_schemes().contains(expected_schemes) {
-                    self.put(proxy)
-                } else {
-                    return Some(proxy);
-                }
+        // newcomers are used in turn until they have enough requests to be ranked
+        if let Some(index) = self.newcomers.iter().position(&usable) {
+            if let Some(mut proxy) = self.newcomers.remove(index) {
+                let taken = proxy.take();
+                self.newcomers.push_back(proxy);
+                return Some(taken);
             }
         }
+
+        let (indices, candidates): (Vec<usize>, Vec<&SimpleProxy>) = self
+            .pool
+            .iter()
+            .enumerate()
+            .filter(|(_, proxy)| usable(proxy))
+            .unzip();
+        if let Some(index) = self.strategy.select(&candidates) {
+            return Some(self.pool[indices[index]].take());
+        }
+        self.import(scheme, selector, tried)
+    }
+
+    /// Takes the proxy at `host:port` if it is still in the pool and serves `scheme`.
+    pub fn get_by_addr(
+        &mut self,
+        host: &str,
+        port: u16,
+        scheme: ProxyProtocol,
+        selector: &Selector,
+    ) -> Option<SimpleProxy> {
+        self.newcomers
+            .iter_mut()
+            .chain(self.pool.iter_mut())
+            .find(|proxy| proxy.host == host && proxy.port == port)
+            .filter(|proxy| proxy.schemes.contains(&scheme) && selector.matches(proxy))
+            .map(|proxy| proxy.take())
     }
 
-    pub fn put(&mut self, proxy: SimpleProxy) {
-        let is_exceed_time = proxy.error_rate() > self.max_error_rate
-            || proxy.avg_resp_time() > self.max_avg_resp_time;
+    /// Moves found proxies into the pool until one serves `expected_scheme`
+    /// and matches `selector`. Does not wait for new proxies to be found.
+    pub fn import(
+        &mut self,
+        expected_scheme: ProxyProtocol,
+        selector: &Selector,
+        tried: &HashSet<String>,
+    ) -> Option<SimpleProxy> {
+        while let Ok(proxy) = LIVE_PROXIES.pop() {
+            let mut proxy = SimpleProxy::from(&proxy);
+            if self.is_banned(&proxy.host, proxy.port) {
+                continue;
+            }
+            let matched = proxy.get_schemes().contains(&expected_scheme)
+                && selector.matches(&proxy)
+                && !tried.contains(&host_port(&proxy.host, proxy.port));
+            if matched {
+                let taken = proxy.take();
+                self.put(proxy);
+                return Some(taken);
+            }
+            self.put(proxy);
+        }
+        None
+    }
 
+    pub fn put(&mut self, mut proxy: SimpleProxy) {
+        // proxies added by hand come without schemes, `get` filters on them
+        proxy.get_schemes();
         if proxy.request_stat < self.min_req_proxy {
             log::debug!("{} added to newcomers", proxy.as_text());
             self.newcomers.push_back(proxy)
-        } else if proxy.request_stat >= self.min_req_proxy && is_exceed_time {
+        } else if self.is_exceeded(&proxy) {
             log::debug!("{} removed from ProxyPool", proxy.as_text());
         } else {
             log::debug!("{} added to pool", proxy.as_text());
             self.pool.push(proxy)
         }
+        self.update_metrics();
     }
 
-    pub fn remove(&mut self, host: &str, port: u16) -> Option<SimpleProxy> {
-        for index in 0..self.newcomers.len() {
-            let proxy = self.newcomers.pop_front().unwrap();
-            if proxy.host == host && proxy.port == port {
-                self.newcomers.remove(index);
-                return Some(proxy);
-            } else {
-                self.newcomers.push_back(proxy)
+    /// Hands back a proxy taken with [`ProxyPool::get`], counting the request
+    /// and the error it failed with, if any.
+    pub fn release(&mut self, proxy: &SimpleProxy, error: Option<&str>) {
+        self.update(proxy, |entry| {
+            entry.connections = entry.connections.saturating_sub(1);
+            entry.request_stat += 1;
+            if let Some(error) = error {
+                *entry.error_stat.entry(error.to_string()).or_insert(0) += 1;
+            }
+        });
+    }
+
+    /// Applies a health check of `proxy`, removing it when it failed.
+    pub fn record_check(&mut self, proxy: &SimpleProxy, checked: &Proxy) {
+        if !checked.is_working {
+            if self.remove(&proxy.host, proxy.port).is_some() {
+                log::debug!("{} failed a health check", proxy.as_text());
             }
+            return;
         }
-        let mut cache = VecDeque::new();
-        while !self.pool.is_empty() {
-            if let Some(proxy) = self.pool.pop() {
-                if proxy.host == host && proxy.port == port {
-                    self.pool.extend(cache);
-                    return Some(proxy);
-                } else {
-                    cache.push_back(proxy)
+        self.update(proxy, |entry| {
+            entry.request_stat += 1;
+            entry.runtimes.push(checked.avg_resp_time());
+            entry.types = checked.types.clone();
+            entry.schemes.clear();
+            entry.get_schemes();
+        });
+    }
+
+    /// Proxies of the pool and newcomers, as they are now.
+    pub fn snapshot(&self) -> Vec<SimpleProxy> {
+        self.newcomers
+            .iter()
+            .chain(self.pool.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Updates the entry of `proxy` with `record`, then promotes newcomers
+    /// with enough requests and removes proxies that exceed the limits.
+    fn update<F: FnOnce(&mut SimpleProxy)>(&mut self, proxy: &SimpleProxy, record: F) {
+        if let Some(index) = self.newcomers.iter().position(|p| p == proxy) {
+            record(&mut self.newcomers[index]);
+            if self.newcomers[index].request_stat >= self.min_req_proxy {
+                if let Some(entry) = self.newcomers.remove(index) {
+                    self.put(entry);
                 }
-            } else {
-                break;
+            }
+        } else if let Some(index) = self.pool.iter().position(|p| p == proxy) {
+            record(&mut self.pool[index]);
+            if self.is_exceeded(&self.pool[index]) {
+                log::debug!("{} removed from ProxyPool", proxy.as_text());
+                self.pool.remove(index);
             }
         }
-        self.pool.extend(cache);
+        self.update_metrics();
+    }
 
-        None
+    pub fn remove(&mut self, host: &str, port: u16) -> Option<SimpleProxy> {
+        let removed = if let Some(index) = self
+            .newcomers
+            .iter()
+            .position(|proxy| proxy.host == host && proxy.port == port)
+        {
+            self.newcomers.remove(index)
+        } else if let Some(index) = self
+            .pool
+            .iter()
+            .position(|proxy| proxy.host == host && proxy.port == port)
+        {
+            Some(self.pool.remove(index))
+        } else {
+            None
+        };
+        self.update_metrics();
+        removed
+    }
+
+    /// Removes the proxy at `host:port` and keeps it from being imported again.
+    pub fn ban(&mut self, host: &str, port: u16) -> Option<SimpleProxy> {
+        self.banned.insert(host_port(host, port));
+        self.remove(host, port)
+    }
+
+    pub fn is_banned(&self, host: &str, port: u16) -> bool {
+        self.banned.contains(&host_port(host, port))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pool.is_empty() && self.newcomers.is_empty()
+    }
+
+    pub fn contains(&self, host: &str, port: u16) -> bool {
+        self.newcomers
+            .iter()
+            .chain(self.pool.iter())
+            .any(|proxy| proxy.host == host && proxy.port == port)
+    }
+
+    /// Ranked proxies.
+    pub fn pool(&self) -> &[SimpleProxy] {
+        &self.pool
+    }
+
+    /// Proxies that do not have enough requests to be ranked yet.
+    pub fn newcomers(&self) -> impl Iterator<Item = &SimpleProxy> {
+        self.newcomers.iter()
+    }
+
+    fn update_metrics(&self) {
+        POOL_SIZE
+            .with_label_values(&["pool"])
+            .set(self.pool.len() as i64);
+        POOL_SIZE
+            .with_label_values(&["newcomers"])
+            .set(self.newcomers.len() as i64);
+    }
+
+    fn is_exceeded(&self, proxy: &SimpleProxy) -> bool {
+        proxy.error_rate() > self.max_error_rate || proxy.avg_resp_time() > self.max_avg_resp_time
+    }
+}
+
+impl Default for ProxyPool {
+    fn default() -> Self {
+        Self::new()
     }
 }
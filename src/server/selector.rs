@@ -0,0 +1,119 @@
+use clap::ValueEnum;
+use hyper::HeaderMap;
+
+use super::{client::Client, proxy_pool::SimpleProxy};
+use crate::types::{AnonymityLevel, ProxyProtocol};
+
+/// Prefix of the headers this server reads, they are not forwarded upstream.
+pub const HEADER_PREFIX: &str = "x-proxy-";
+
+const KEYS: [&str; 4] = ["country", "level", "protocol", "latency"];
+
+/// Requirements a client puts on the upstream serving its request.
+///
+/// They are read from `X-Proxy-Country`, `X-Proxy-Level`, `X-Proxy-Protocol`
+/// and `X-Proxy-Latency` headers, or from a proxy-auth username such as
+/// `country-US_level-High`. Several values are separated by commas.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selector {
+    pub countries: Vec<String>,
+    pub levels: Vec<AnonymityLevel>,
+    pub protocols: Vec<ProxyProtocol>,
+    /// The maximum average response time in seconds.
+    pub max_latency: Option<f64>,
+}
+
+impl Selector {
+    /// Selector of a request, headers take precedence over the username.
+    pub fn from_request(client: &Client, headers: Option<&HeaderMap>) -> Result<Self, String> {
+        let mut selector = match &client.credentials {
+            Some(credentials) => Self::from_username(&credentials.username)?,
+            None => Self::default(),
+        };
+
+        for (name, value) in headers.into_iter().flatten() {
+            if let Some(key) = name.as_str().strip_prefix(HEADER_PREFIX) {
+                let value = value
+                    .to_str()
+                    .map_err(|_| format!("invalid value of {}", name))?;
+                selector.set(key, value)?;
+            }
+        }
+        Ok(selector)
+    }
+
+    /// Selector encoded in a username, parts that are not selectors are ignored.
+    pub fn from_username(username: &str) -> Result<Self, String> {
+        let mut selector = Self::default();
+        for part in username.split('_') {
+            if let Some((key, value)) = part.split_once('-') {
+                selector.set(key, value)?;
+            }
+        }
+        Ok(selector)
+    }
+
+    /// Whether `suffix` of a username only holds valid selectors, as in
+    /// `country-US_level-High`.
+    pub fn is_suffix(suffix: &str) -> bool {
+        let mut selector = Self::default();
+        suffix.split('_').all(|part| {
+            part.split_once('-').is_some_and(|(key, value)| {
+                KEYS.contains(&key.to_ascii_lowercase().as_str())
+                    && selector.set(key, value).is_ok()
+            })
+        })
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let values = value.split(',').map(str::trim).filter(|v| !v.is_empty());
+        match key.to_ascii_lowercase().as_str() {
+            "country" => self.countries = values.map(str::to_uppercase).collect(),
+            "level" => {
+                self.levels = values
+                    .map(|v| AnonymityLevel::from_str(v, true))
+                    .collect::<Result<_, _>>()?
+            }
+            "protocol" => {
+                self.protocols = values
+                    .map(|v| v.parse::<ProxyProtocol>())
+                    .collect::<Result<_, _>>()?
+            }
+            "latency" => {
+                self.max_latency = Some(
+                    value
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("invalid latency: {}", value))?,
+                )
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn matches(&self, proxy: &SimpleProxy) -> bool {
+        (self.countries.is_empty()
+            || self
+                .countries
+                .iter()
+                .any(|country| country.eq_ignore_ascii_case(&proxy.geo.iso_code)))
+            && (self.levels.is_empty()
+                || proxy
+                    .types
+                    .iter()
+                    .any(|(_, level)| level.is_some_and(|level| self.levels.contains(&level))))
+            && (self.protocols.is_empty()
+                || proxy
+                    .types
+                    .iter()
+                    .any(|(proto, _)| self.protocols.contains(proto)))
+            && self
+                .max_latency
+                .is_none_or(|max_latency| proxy.avg_resp_time() <= max_latency)
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+
+use super::{client::Client, proxy_pool::SimpleProxy};
+
+/// What identifies the session of a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SessionKey {
+    /// The `X-Proxy-Session` header
+    Header,
+    /// The proxy-auth username
+    Username,
+    /// The ip address of the client
+    ClientIp,
+}
+
+impl SessionKey {
+    /// Session id of `client`, if it provided this key.
+    pub fn session_id(&self, client: &Client) -> Option<String> {
+        match self {
+            SessionKey::Header => client
+                .session
+                .as_ref()
+                .map(|session| format!("header:{}", session)),
+            SessionKey::Username => client
+                .credentials
+                .as_ref()
+                .map(|credentials| format!("username:{}", credentials.username)),
+            SessionKey::ClientIp => Some(format!("ip:{}", client.addr.ip())),
+        }
+    }
+}
+
+/// Session id from the first of `keys` the client provided.
+pub fn session_id(keys: &[SessionKey], client: &Client) -> Option<String> {
+    keys.iter().find_map(|key| key.session_id(client))
+}
+
+#[derive(Debug, Clone)]
+struct Pin {
+    host: String,
+    port: u16,
+    expires: Instant,
+}
+
+/// Upstreams pinned to client sessions.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    pins: HashMap<String, Pin>,
+    /// How long a session keeps its upstream after its last request.
+    pub ttl: Duration,
+}
+
+impl Sessions {
+    /// Host and port of the upstream pinned to `session`, extending its lifetime.
+    pub fn get(&mut self, session: &str) -> Option<(String, u16)> {
+        let now = Instant::now();
+        match self.pins.get_mut(session) {
+            Some(pin) if pin.expires > now => {
+                pin.expires = now + self.ttl;
+                Some((pin.host.clone(), pin.port))
+            }
+            Some(_) => {
+                self.pins.remove(session);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn pin(&mut self, session: &str, proxy: &SimpleProxy) {
+        let now = Instant::now();
+        self.pins.retain(|_, pin| pin.expires > now);
+        self.pins.insert(
+            session.to_string(),
+            Pin {
+                host: proxy.host.clone(),
+                port: proxy.port,
+                expires: now + self.ttl,
+            },
+        );
+    }
+
+    pub fn unpin(&mut self, session: &str) {
+        self.pins.remove(session);
+    }
+}
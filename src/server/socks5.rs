@@ -0,0 +1,137 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::{
+    client::Client, connect_upstream, error_kind, open_tunnel, selector::Selector,
+    session::session_id, tunnel_scheme, Server, POOL,
+};
+use crate::{
+    metrics::{self, SERVER_REQUESTS},
+    negotiators::socks5::read_target,
+    proxy::Credentials,
+};
+
+const NO_AUTH: u8 = 0x00;
+const USERNAME_PASSWORD: u8 = 0x02;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+
+const REP_SUCCEEDED: u8 = 0x00;
+const REP_GENERAL_FAILURE: u8 = 0x01;
+const REP_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+
+/// Serves a SOCKS5 client (RFC 1928), relaying its CONNECT through an upstream
+/// from the pool.
+pub async fn handle_socks5_stream(
+    server: Arc<Server>,
+    mut stream: TcpStream,
+    addr: SocketAddr,
+) -> io::Result<()> {
+    let mut client = Client::new(addr);
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    let mut methods = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut methods).await?;
+
+    // username/password is also accepted without users, the username may
+    // carry selectors
+    let method = if header[0] != 0x05 {
+        NO_ACCEPTABLE_METHODS
+    } else if server.users.is_empty() && methods.contains(&NO_AUTH) {
+        NO_AUTH
+    } else if methods.contains(&USERNAME_PASSWORD) {
+        USERNAME_PASSWORD
+    } else {
+        NO_ACCEPTABLE_METHODS
+    };
+    stream.write_all(&[0x05, method]).await?;
+    match method {
+        NO_ACCEPTABLE_METHODS => return Ok(()),
+        USERNAME_PASSWORD => {
+            client.credentials = Some(read_credentials(&mut stream).await?);
+            if !server.is_authorized(&client) {
+                log::warn!("Unauthorized client {}", addr);
+                SERVER_REQUESTS
+                    .with_label_values(&["socks5", "unauthorized"])
+                    .inc();
+                return stream.write_all(&[0x01, 0x01]).await;
+            }
+            stream.write_all(&[0x01, 0x00]).await?;
+        }
+        _ => {}
+    }
+
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await?;
+    let target = read_target(&mut stream, request[3]).await?;
+    if request[1] != CMD_CONNECT {
+        return send_reply(&mut stream, REP_COMMAND_NOT_SUPPORTED).await;
+    }
+
+    let session = session_id(&server.session_keys, &client);
+    let selector = match Selector::from_request(&client, None) {
+        Ok(selector) => selector,
+        Err(err) => {
+            log::warn!("Invalid selector from {}: {}", addr, err);
+            return send_reply(&mut stream, REP_GENERAL_FAILURE).await;
+        }
+    };
+    let target = &target;
+    match connect_upstream(
+        tunnel_scheme(target),
+        server.max_attempts,
+        session.as_deref(),
+        &selector,
+        |proxy| async move { open_tunnel(&proxy, target).await },
+    )
+    .await
+    {
+        Ok((proxy, (mut proxy_stream, _))) => {
+            let result = match send_reply(&mut stream, REP_SUCCEEDED).await {
+                Ok(_) => tokio::io::copy_bidirectional(&mut stream, &mut proxy_stream)
+                    .await
+                    .map(|(sent, received)| metrics::record_tunnel(sent, received)),
+                Err(err) => Err(err),
+            };
+            let error = result.as_ref().err().map(|err| error_kind(err));
+            POOL.lock().release(&proxy, error);
+            result?;
+        }
+        Err(err) => {
+            log::error!("Failed to connect proxy: {}", err);
+            send_reply(&mut stream, REP_GENERAL_FAILURE).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the username/password sub-negotiation, see RFC 1929.
+async fn read_credentials(stream: &mut TcpStream) -> io::Result<Credentials> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    let mut username = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut username).await?;
+    let mut password = vec![0u8; stream.read_u8().await? as usize];
+    stream.read_exact(&mut password).await?;
+    Ok(Credentials::new(
+        &String::from_utf8_lossy(&username),
+        &String::from_utf8_lossy(&password),
+    ))
+}
+
+/// Replies with an unspecified bound address, clients do not rely on it.
+async fn send_reply(stream: &mut TcpStream, rep: u8) -> io::Result<()> {
+    let status = match rep {
+        REP_SUCCEEDED => "succeeded",
+        REP_COMMAND_NOT_SUPPORTED => "command_not_supported",
+        _ => "general_failure",
+    };
+    SERVER_REQUESTS.with_label_values(&["socks5", status]).inc();
+    stream
+        .write_all(&[0x05, rep, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+        .await
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use rand::{distributions::WeightedIndex, prelude::Distribution, thread_rng, Rng};
+
+use super::proxy_pool::SimpleProxy;
+
+/// Picks which upstream of the pool serves the next request.
+pub trait SelectionStrategy: fmt::Debug + Send {
+    /// Index of the chosen proxy in `candidates`, `None` when it is empty.
+    fn select(&mut self, candidates: &[&SimpleProxy]) -> Option<usize>;
+}
+
+/// Selection strategies available from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Strategy {
+    /// Lowest error rate, then lowest average response time
+    #[default]
+    Best,
+    /// Each proxy in turn
+    RoundRobin,
+    /// Any proxy, uniformly
+    Random,
+    /// Random, favoring proxies with a low average response time
+    WeightedLatency,
+    /// The proxy that has been idle the longest
+    LeastRecentlyUsed,
+    /// The proxy with the fewest requests in flight
+    LeastConnections,
+}
+
+impl Strategy {
+    pub fn build(&self) -> Box<dyn SelectionStrategy> {
+        match self {
+            Strategy::Best => Box::new(Best),
+            Strategy::RoundRobin => Box::<RoundRobin>::default(),
+            Strategy::Random => Box::new(Random),
+            Strategy::WeightedLatency => Box::new(WeightedLatency),
+            Strategy::LeastRecentlyUsed => Box::new(LeastRecentlyUsed),
+            Strategy::LeastConnections => Box::new(LeastConnections),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Best;
+
+impl SelectionStrategy for Best {
+    fn select(&mut self, candidates: &[&SimpleProxy]) -> Option<usize> {
+        candidates
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.cmp(b))
+            .map(|(index, _)| index)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl SelectionStrategy for RoundRobin {
+    fn select(&mut self, candidates: &[&SimpleProxy]) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        let index = self.next % candidates.len();
+        self.next = index + 1;
+        Some(index)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Random;
+
+impl SelectionStrategy for Random {
+    fn select(&mut self, candidates: &[&SimpleProxy]) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(thread_rng().gen_range(0..candidates.len()))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WeightedLatency;
+
+impl SelectionStrategy for WeightedLatency {
+    fn select(&mut self, candidates: &[&SimpleProxy]) -> Option<usize> {
+        // proxies without measurements weigh as much as a 100ms one
+        let weights = candidates
+            .iter()
+            .map(|proxy| 1.0 / proxy.avg_resp_time().max(0.1));
+        WeightedIndex::new(weights)
+            .ok()
+            .map(|dist| dist.sample(&mut thread_rng()))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LeastRecentlyUsed;
+
+impl SelectionStrategy for LeastRecentlyUsed {
+    fn select(&mut self, candidates: &[&SimpleProxy]) -> Option<usize> {
+        // `None` sorts first, so unused proxies win
+        candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, proxy)| proxy.last_used)
+            .map(|(index, _)| index)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LeastConnections;
+
+impl SelectionStrategy for LeastConnections {
+    fn select(&mut self, candidates: &[&SimpleProxy]) -> Option<usize> {
+        candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, proxy)| proxy.connections)
+            .map(|(index, _)| index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::types::ProxyProtocol;
+
+    fn pool(size: u16) -> Vec<SimpleProxy> {
+        (0..size)
+            .map(|i| SimpleProxy::new("127.0.0.1", 8000 + i, vec![ProxyProtocol::Http]))
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_cycles() {
+        let pool = pool(3);
+        let candidates: Vec<_> = pool.iter().collect();
+        let mut strategy = RoundRobin::default();
+        let picks: Vec<_> = (0..7)
+            .map(|_| strategy.select(&candidates).unwrap())
+            .collect();
+        assert_eq!(picks, vec![0, 1, 2, 0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn least_connections_picks_the_idlest() {
+        let mut pool = pool(3);
+        pool[0].connections = 4;
+        pool[1].connections = 1;
+        pool[2].connections = 2;
+        let candidates: Vec<_> = pool.iter().collect();
+        assert_eq!(LeastConnections.select(&candidates), Some(1));
+    }
+
+    #[test]
+    fn least_recently_used_prefers_unused_then_oldest() {
+        let now = Instant::now();
+        let mut pool = pool(3);
+        pool[0].last_used = Some(now);
+        pool[1].last_used = Some(now - Duration::from_secs(10));
+        pool[2].last_used = Some(now - Duration::from_secs(5));
+        let candidates: Vec<_> = pool.iter().collect();
+        assert_eq!(LeastRecentlyUsed.select(&candidates), Some(1));
+
+        pool[2].last_used = None;
+        let candidates: Vec<_> = pool.iter().collect();
+        assert_eq!(LeastRecentlyUsed.select(&candidates), Some(2));
+    }
+
+    #[test]
+    fn weighted_latency_handles_empty_and_single() {
+        assert_eq!(WeightedLatency.select(&[]), None);
+
+        let mut pool = pool(1);
+        pool[0].runtimes = vec![0.5];
+        let candidates: Vec<_> = pool.iter().collect();
+        for _ in 0..10 {
+            assert_eq!(WeightedLatency.select(&candidates), Some(0));
+        }
+    }
+
+    #[test]
+    fn best_picks_the_lowest_error_rate() {
+        let mut pool = pool(3);
+        for (proxy, errors) in pool.iter_mut().zip([3, 0, 1]) {
+            proxy.request_stat = 4;
+            if errors > 0 {
+                proxy
+                    .error_stat
+                    .insert("connect_refused".to_string(), errors);
+            }
+        }
+        let candidates: Vec<_> = pool.iter().collect();
+        assert_eq!(Best.select(&candidates), Some(1));
+    }
+
+    #[test]
+    fn empty_pool_selects_nothing() {
+        assert_eq!(Best.select(&[]), None);
+        assert_eq!(RoundRobin::default().select(&[]), None);
+        assert_eq!(Random.select(&[]), None);
+        assert_eq!(LeastRecentlyUsed.select(&[]), None);
+        assert_eq!(LeastConnections.select(&[]), None);
+    }
+}
@@ -0,0 +1,278 @@
+//! On-disk history of proxies, kept across runs in a SQLite database.
+
+use std::{
+    fmt, panic,
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use rusqlite::{params, Connection};
+use tokio::task;
+
+use crate::{
+    proxy::{Credentials, Proxy},
+    types::ProxyProtocol,
+};
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+
+    CREATE TABLE IF NOT EXISTS proxies (
+        host TEXT NOT NULL,
+        port INTEGER NOT NULL,
+        hostname TEXT,
+        username TEXT,
+        password TEXT,
+        provider TEXT,
+        country TEXT,
+        types TEXT NOT NULL,
+        first_seen INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL,
+        last_working INTEGER,
+        working INTEGER NOT NULL,
+        checks INTEGER NOT NULL,
+        passed INTEGER NOT NULL,
+        PRIMARY KEY (host, port)
+    );
+
+    CREATE TABLE IF NOT EXISTS checks (
+        host TEXT NOT NULL,
+        port INTEGER NOT NULL,
+        checked_at INTEGER NOT NULL,
+        working INTEGER NOT NULL,
+        types TEXT NOT NULL,
+        avg_resp_time REAL NOT NULL,
+        errors TEXT NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS checks_proxy ON checks (host, port);
+";
+
+/// A proxy as remembered by the [`ProxyStore`].
+#[derive(Debug, Clone)]
+pub struct StoredProxy {
+    pub host: String,
+    pub port: u16,
+    pub hostname: Option<String>,
+    pub credentials: Option<Credentials>,
+    pub provider: Option<String>,
+    pub country: Option<String>,
+    /// Protocols that passed the last successful check.
+    pub types: Vec<ProxyProtocol>,
+    /// Unix timestamps in seconds.
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub last_working: Option<u64>,
+    pub checks: u32,
+    pub passed: u32,
+}
+
+impl StoredProxy {
+    /// Share of the checks that passed.
+    pub fn uptime(&self) -> f64 {
+        if self.checks == 0 {
+            return 0.0;
+        }
+        self.passed as f64 / self.checks as f64
+    }
+}
+
+/// The outcome of a check of a proxy, as written by [`ProxyStore::record`].
+#[derive(Debug, Clone)]
+pub struct CheckRecord {
+    pub host: String,
+    pub port: u16,
+    pub hostname: Option<String>,
+    pub credentials: Option<Credentials>,
+    pub provider: Option<String>,
+    pub country: String,
+    pub working: bool,
+    /// Comma separated protocols that passed the check.
+    pub types: String,
+    pub avg_resp_time: f64,
+    /// `error_stat` of the proxy as JSON.
+    pub errors: String,
+}
+
+impl From<&Proxy> for CheckRecord {
+    fn from(proxy: &Proxy) -> Self {
+        Self {
+            host: proxy.host.clone(),
+            port: proxy.port,
+            hostname: proxy.hostname.clone(),
+            credentials: proxy.credentials.clone(),
+            provider: proxy.provider.clone(),
+            country: proxy.geo.iso_code.clone(),
+            working: proxy.is_working,
+            types: proxy
+                .types
+                .iter()
+                .map(|(proto, _)| proto.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+            avg_resp_time: proxy.avg_resp_time(),
+            errors: serde_json::to_string(&proxy.error_stat).unwrap_or_default(),
+        }
+    }
+}
+
+/// Records every check of a proxy, see [`ProxyStore::record`].
+pub struct ProxyStore {
+    conn: Mutex<Connection>,
+    store_credentials: bool,
+}
+
+impl ProxyStore {
+    /// Opens the database at `path`, creating it when missing.
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            store_credentials: false,
+        })
+    }
+
+    /// Also stores the username and password of proxies, in plain text.
+    /// Off by default.
+    pub fn store_credentials(mut self, store: bool) -> Self {
+        self.store_credentials = store;
+        self
+    }
+
+    /// Records the outcome of a check of `proxy`, on a blocking thread so
+    /// that checks running on the runtime do not wait for the disk.
+    pub async fn record_async(self: &Arc<Self>, proxy: &Proxy) -> rusqlite::Result<()> {
+        let store = Arc::clone(self);
+        let check = CheckRecord::from(proxy);
+        match task::spawn_blocking(move || store.write(&check)).await {
+            Ok(result) => result,
+            Err(err) => panic::resume_unwind(err.into_panic()),
+        }
+    }
+
+    /// Records the outcome of a check of `proxy`, blocking the current thread.
+    pub fn record(&self, proxy: &Proxy) -> rusqlite::Result<()> {
+        self.write(&CheckRecord::from(proxy))
+    }
+
+    fn write(&self, check: &CheckRecord) -> rusqlite::Result<()> {
+        let now = now();
+        let working = check.working;
+        let (username, password) = match (&check.credentials, self.store_credentials) {
+            (Some(credentials), true) => (Some(&credentials.username), Some(&credentials.password)),
+            _ => (None, None),
+        };
+
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction()?;
+        // types of a failed check are kept from the last successful one
+        tx.execute(
+            "INSERT INTO proxies (host, port, hostname, username, password, provider, country,
+                types, first_seen, last_seen, last_working, working, checks, passed)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?9, ?10, ?11, 1, ?11)
+             ON CONFLICT (host, port) DO UPDATE SET
+                hostname = COALESCE(excluded.hostname, hostname),
+                username = COALESCE(excluded.username, username),
+                password = COALESCE(excluded.password, password),
+                provider = COALESCE(provider, excluded.provider),
+                country = excluded.country,
+                types = CASE WHEN excluded.working THEN excluded.types ELSE types END,
+                last_seen = excluded.last_seen,
+                last_working = COALESCE(excluded.last_working, last_working),
+                working = excluded.working,
+                checks = checks + 1,
+                passed = passed + excluded.working",
+            params![
+                check.host,
+                check.port,
+                check.hostname,
+                username,
+                password,
+                check.provider,
+                check.country,
+                check.types,
+                now,
+                working.then_some(now),
+                working,
+            ],
+        )?;
+        tx.execute(
+            "INSERT INTO checks (host, port, checked_at, working, types, avg_resp_time, errors)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                check.host,
+                check.port,
+                now,
+                working,
+                check.types,
+                check.avg_resp_time,
+                check.errors,
+            ],
+        )?;
+        tx.commit()
+    }
+
+    /// Proxies that passed their last check, the most reliable first.
+    pub fn known_good(&self) -> rusqlite::Result<Vec<StoredProxy>> {
+        let conn = self.conn.lock();
+        let mut statement = conn.prepare(&format!(
+            "{} WHERE working ORDER BY CAST(passed AS REAL) / checks DESC, last_working DESC",
+            SELECT_PROXIES
+        ))?;
+        let proxies = statement.query_map([], stored_proxy)?.collect();
+        proxies
+    }
+}
+
+impl fmt::Debug for ProxyStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyStore").finish_non_exhaustive()
+    }
+}
+
+const SELECT_PROXIES: &str = "SELECT host, port, hostname, username, password, provider,
+    country, types, first_seen, last_seen, last_working, checks, passed FROM proxies";
+
+fn stored_proxy(row: &rusqlite::Row) -> rusqlite::Result<StoredProxy> {
+    let username: Option<String> = row.get(3)?;
+    let password: Option<String> = row.get(4)?;
+    let types: String = row.get(7)?;
+    Ok(StoredProxy {
+        host: row.get(0)?,
+        port: row.get(1)?,
+        hostname: row.get(2)?,
+        credentials: username
+            .zip(password)
+            .map(|(username, password)| Credentials::new(&username, &password)),
+        provider: row.get(5)?,
+        country: row.get(6)?,
+        types: types
+            .split(',')
+            .filter(|proto| !proto.is_empty())
+            .filter_map(|proto| match proto.parse() {
+                Ok(proto) => Some(proto),
+                Err(err) => {
+                    // custom protocols parse once their negotiator is registered
+                    log::warn!("Stored proxy skips {}", err);
+                    None
+                }
+            })
+            .collect(),
+        first_seen: row.get(8)?,
+        last_seen: row.get(9)?,
+        last_working: row.get(10)?,
+        checks: row.get(11)?,
+        passed: row.get(12)?,
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,204 @@
+use std::{fmt, str::FromStr};
+
+use clap::ValueEnum;
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::negotiators::{get_negotiator, NEGOTIATORS};
+
+/// Protocol that a proxy can be checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum, Serialize)]
+#[serde(into = "String")]
+pub enum ProxyProtocol {
+    #[value(name = "HTTP")]
+    Http,
+    #[value(name = "HTTPS")]
+    Https,
+    #[value(name = "SOCKS4")]
+    Socks4,
+    #[value(name = "SOCKS4a")]
+    Socks4a,
+    #[value(name = "SOCKS5")]
+    Socks5,
+    #[value(name = "CONNECT:80")]
+    Connect80,
+    #[value(name = "CONNECT:25")]
+    Connect25,
+    /// Protocol handled by a negotiator registered at runtime.
+    #[value(skip)]
+    Custom(&'static str),
+}
+
+impl ProxyProtocol {
+    /// Built-in protocols, in the order the checker tries them.
+    pub const BUILTIN: [ProxyProtocol; 7] = [
+        ProxyProtocol::Connect80,
+        ProxyProtocol::Connect25,
+        ProxyProtocol::Socks5,
+        ProxyProtocol::Socks4,
+        ProxyProtocol::Socks4a,
+        ProxyProtocol::Https,
+        ProxyProtocol::Http,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProxyProtocol::Http => "HTTP",
+            ProxyProtocol::Https => "HTTPS",
+            ProxyProtocol::Socks4 => "SOCKS4",
+            ProxyProtocol::Socks4a => "SOCKS4a",
+            ProxyProtocol::Socks5 => "SOCKS5",
+            ProxyProtocol::Connect80 => "CONNECT:80",
+            ProxyProtocol::Connect25 => "CONNECT:25",
+            ProxyProtocol::Custom(name) => name,
+        }
+    }
+
+    /// Schemes a client can be served through this protocol in `serve` mode,
+    /// `Connect80` and `Connect25` standing for tunnels to those ports. Custom
+    /// protocols serve those their negotiator declares.
+    pub fn schemes(&self) -> Vec<ProxyProtocol> {
+        match self {
+            ProxyProtocol::Http => vec![ProxyProtocol::Http],
+            ProxyProtocol::Connect80 => vec![ProxyProtocol::Http, ProxyProtocol::Connect80],
+            ProxyProtocol::Connect25 => vec![ProxyProtocol::Connect25],
+            ProxyProtocol::Https => vec![
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Connect25,
+            ],
+            ProxyProtocol::Socks4 | ProxyProtocol::Socks4a | ProxyProtocol::Socks5 => vec![
+                ProxyProtocol::Http,
+                ProxyProtocol::Https,
+                ProxyProtocol::Connect80,
+                ProxyProtocol::Connect25,
+            ],
+            ProxyProtocol::Custom(_) => get_negotiator(self)
+                .map(|negotiator| negotiator.schemes())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Display for ProxyProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ProxyProtocol {
+    type Err = String;
+
+    /// Parses the name of a built-in protocol or of one with a registered
+    /// negotiator.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ProxyProtocol::BUILTIN
+            .into_iter()
+            .chain(NEGOTIATORS.read().protocols())
+            .find(|proto| proto.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown protocol: {}", s))
+    }
+}
+
+impl From<ProxyProtocol> for String {
+    fn from(proto: ProxyProtocol) -> Self {
+        proto.as_str().to_string()
+    }
+}
+
+impl TryFrom<String> for ProxyProtocol {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+// not derived, `Custom` would tie the input lifetime to 'static
+impl<'de> Deserialize<'de> for ProxyProtocol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Anonymity level of a proxy as seen by the judge.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum, Serialize, Deserialize,
+)]
+pub enum AnonymityLevel {
+    #[value(name = "Transparent")]
+    Transparent,
+    #[value(name = "Anonymous")]
+    Anonymous,
+    #[value(name = "High")]
+    High,
+}
+
+impl AnonymityLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnonymityLevel::Transparent => "Transparent",
+            AnonymityLevel::Anonymous => "Anonymous",
+            AnonymityLevel::High => "High",
+        }
+    }
+}
+
+impl fmt::Display for AnonymityLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        judge::Judge,
+        negotiators::{register_negotiator, Negotiator},
+        proxy::Proxy,
+    };
+
+    struct Obfs;
+
+    #[async_trait]
+    impl Negotiator for Obfs {
+        fn name(&self) -> &str {
+            "OBFS"
+        }
+
+        fn check_anon_lvl(&self) -> bool {
+            false
+        }
+
+        fn use_full_path(&self) -> bool {
+            false
+        }
+
+        async fn negotiate(&self, _proxy: &mut Proxy, _judge: &Judge) -> bool {
+            false
+        }
+
+        fn schemes(&self) -> Vec<ProxyProtocol> {
+            vec![ProxyProtocol::Https]
+        }
+    }
+
+    #[test]
+    fn registered_protocols_round_trip() {
+        let obfs = ProxyProtocol::Custom("OBFS");
+        assert!("OBFS".parse::<ProxyProtocol>().is_err());
+
+        register_negotiator(obfs, Obfs);
+        assert_eq!("obfs".parse::<ProxyProtocol>(), Ok(obfs));
+        let json = serde_json::to_string(&obfs).unwrap();
+        assert_eq!(serde_json::from_str::<ProxyProtocol>(&json).unwrap(), obfs);
+        assert_eq!(obfs.schemes(), vec![ProxyProtocol::Https]);
+        assert!(ProxyProtocol::Socks5
+            .schemes()
+            .contains(&ProxyProtocol::Https));
+    }
+}
@@ -5,10 +5,10 @@ use hyper::{body::HttpBody, header::CONTENT_LENGTH, Body, Request};
 use indicatif::{ProgressBar, ProgressStyle};
 use lazy_static::lazy_static;
 use maxminddb::Reader;
-use parking_lot::Mutex;
 use tokio::{
     fs::{self, File},
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::watch,
 };
 
 use super::http::{hyper_client, random_useragent};
@@ -20,14 +20,19 @@ const GEOLITEDB_CHECKSUM_URL: &str =
     "https://raw.githubusercontent.com/zevtyardt/proxy.rs/main/data/Geolite2-City.mmdb.checksum";
 
 lazy_static! {
-    pub static ref DOWNLOADING: Mutex<bool> = Mutex::new(false);
+    static ref DOWNLOADING: watch::Sender<bool> = watch::channel(false).0;
+}
+
+/// Waits until the database being downloaded, if any, is written.
+pub async fn wait_for_download() {
+    let _ = DOWNLOADING
+        .subscribe()
+        .wait_for(|downloading| !downloading)
+        .await;
 }
 
 async fn download_geolite_db() {
-    {
-        let mut isdown = DOWNLOADING.lock();
-        *isdown = true;
-    }
+    DOWNLOADING.send_replace(true);
 
     let bar = ProgressBar::new(0);
     bar.set_style(
@@ -61,8 +66,7 @@ async fn download_geolite_db() {
             }
         }
     }
-    let mut isdown = DOWNLOADING.lock();
-    *isdown = false;
+    DOWNLOADING.send_replace(false);
 
     bar.finish();
 }
@@ -24,7 +24,7 @@ pub fn get_headers(random_value: bool) -> (BTreeMap<String, String>, String) {
     let ua = random_useragent(random_value);
 
     let ua_c = ua.clone();
-    let rv = ua_c.split('/').last().unwrap();
+    let rv = ua_c.split('/').next_back().unwrap();
     let mut headers = BTreeMap::new();
 
     headers.insert("User-Agent".to_string(), ua);
@@ -45,7 +45,7 @@ impl RequestParser {
             }
         }
 
-        if let Some(host) = self.headers.get(&"host".to_string()) {
+        if let Some(host) = self.headers.get("host") {
             return Some(host.to_string());
         }
 
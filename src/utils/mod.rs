@@ -1,10 +1,16 @@
 pub mod geolite_database;
 pub mod http;
+pub mod net;
+pub mod queue;
 pub mod serializer;
 pub mod update;
 
-macro_rules! vec_of_strings {
-    ($($x:expr),*) => (vec![$($x.to_string()),*]);
-}
+use std::net::IpAddr;
 
-pub(crate) use vec_of_strings;
+/// Formats `host:port`, wrapping ipv6 addresses in brackets.
+pub fn host_port(host: &str, port: u16) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{}]:{}", host, port),
+        _ => format!("{}:{}", host, port),
+    }
+}
@@ -0,0 +1,32 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    net::SocketAddr,
+    time::Duration,
+};
+
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use tokio::{net::TcpStream, time};
+
+/// Delay before the next address is tried while earlier attempts are pending.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Connects to the first reachable address, starting a new attempt every
+/// `ATTEMPT_DELAY` without cancelling the pending ones (happy eyeballs).
+pub async fn connect_any(addrs: Vec<SocketAddr>) -> Result<TcpStream> {
+    let mut attempts = FuturesUnordered::new();
+    for (i, addr) in addrs.into_iter().enumerate() {
+        attempts.push(async move {
+            time::sleep(ATTEMPT_DELAY * i as u32).await;
+            TcpStream::connect(addr).await
+        });
+    }
+
+    let mut last_error = Error::new(ErrorKind::InvalidInput, "No addresses to connect to");
+    while let Some(result) = attempts.next().await {
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
@@ -0,0 +1,88 @@
+use std::pin::pin;
+
+use concurrent_queue::{ConcurrentQueue, PopError, PushError};
+use tokio::sync::Notify;
+
+/// A [`ConcurrentQueue`] that tasks can wait on without spinning.
+#[derive(Debug)]
+pub struct Queue<T> {
+    queue: ConcurrentQueue<T>,
+    pushed: Notify,
+    popped: Notify,
+}
+
+impl<T> Queue<T> {
+    pub fn bounded(cap: usize) -> Self {
+        Self::new(ConcurrentQueue::bounded(cap))
+    }
+
+    pub fn unbounded() -> Self {
+        Self::new(ConcurrentQueue::unbounded())
+    }
+
+    fn new(queue: ConcurrentQueue<T>) -> Self {
+        Self {
+            queue,
+            pushed: Notify::new(),
+            popped: Notify::new(),
+        }
+    }
+
+    pub fn push(&self, item: T) -> Result<(), PushError<T>> {
+        self.queue.push(item)?;
+        self.pushed.notify_waiters();
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Result<T, PopError> {
+        let item = self.queue.pop()?;
+        self.popped.notify_waiters();
+        Ok(item)
+    }
+
+    /// Pushes `item`, waiting for room while the queue is full.
+    pub async fn push_wait(&self, mut item: T) -> Result<(), PushError<T>> {
+        loop {
+            // registered before trying, so that a pop in between is not missed
+            let mut popped = pin!(self.popped.notified());
+            popped.as_mut().enable();
+            match self.push(item) {
+                Err(PushError::Full(rejected)) => item = rejected,
+                result => return result,
+            }
+            popped.await;
+        }
+    }
+
+    /// Pops an item, waiting for one while the queue is empty.
+    pub async fn pop_wait(&self) -> T {
+        loop {
+            let mut pushed = pin!(self.pushed.notified());
+            pushed.as_mut().enable();
+            if let Ok(item) = self.pop() {
+                return item;
+            }
+            pushed.await;
+        }
+    }
+
+    /// Waits until the queue has at least one item.
+    pub async fn wait_for_item(&self) {
+        loop {
+            let mut pushed = pin!(self.pushed.notified());
+            pushed.as_mut().enable();
+            if !self.queue.is_empty() {
+                return;
+            }
+            pushed.await;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
@@ -1,36 +1,46 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize)]
+use crate::{
+    proxy::Credentials,
+    types::{AnonymityLevel, ProxyProtocol},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Geo {
     pub country: Country,
     pub region: Region,
     pub city: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Country {
     pub code: String,
     pub name: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Region {
     pub code: String,
     pub name: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyData {
     pub host: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
     pub port: u16,
+    /// Credentials the proxy was found with, needed to check it again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<Credentials>,
     pub geo: Geo,
     pub types: Vec<ProxyType>,
     pub avg_resp_time: f64,
     pub error_rate: f64,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProxyType {
-    pub proxy_type: String,
-    pub level: Option<String>,
+    pub proxy_type: ProxyProtocol,
+    pub level: Option<AnonymityLevel>,
 }